@@ -0,0 +1,374 @@
+//! Brush shapes for painting many cells with one call, and strokes that
+//! join a pointer's samples into a solid line.
+//!
+//! Shapes may reach past the edges of the world and are clipped to it. The
+//! wall border is never painted over. Each call, and each whole stroke, is a
+//! single undo step.
+//!
+//! The spawn mode and spray density narrow down which cells of a shape, or of
+//! a single `spawn`, are actually written.
+
+use std::ops::Range;
+
+use rand::Rng;
+
+use crate::particle::ParticleType;
+use crate::simulation::is_border;
+use crate::simulation::Simulation;
+
+/// Which cells brushes and `spawn` may write to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpawnMode {
+    /// Replaces whatever is there.
+    Overwrite,
+    /// Only fills empty cells, leaving everything else in place.
+    OnlyEmpty,
+    /// Only replaces cells holding the given material.
+    Replace(ParticleType),
+}
+
+/// The part of the `length` positions from `start` that lies within
+/// `0..size`, so loops over a shape never walk the cells off the world.
+pub(crate) fn clip(start: isize, length: usize, size: usize) -> Range<isize> {
+    let end = (start as i128 + length as i128).min(size as i128) as isize;
+    start.max(0)..end
+}
+
+impl SpawnMode {
+    fn admits(self, current: ParticleType) -> bool {
+        match self {
+            SpawnMode::Overwrite => true,
+            SpawnMode::OnlyEmpty => current == ParticleType::Empty,
+            SpawnMode::Replace(source) => current == source,
+        }
+    }
+}
+
+/// A stroke in progress.
+pub(crate) struct Stroke {
+    p_type: ParticleType,
+    radius: usize,
+    /// The last point stamped and its radius.
+    last: Option<((isize, isize), usize)>,
+}
+
+impl Simulation {
+    pub fn set_spawn_mode(&mut self, spawn_mode: SpawnMode) {
+        self.spawn_mode = spawn_mode;
+    }
+
+    pub fn spawn_mode(&self) -> SpawnMode {
+        self.spawn_mode
+    }
+
+    /// Sets the percentage of cells, from 0 to 100, that brushes and `spawn`
+    /// write, picked at random.
+    pub fn set_spray_density(&mut self, percent: u8) {
+        self.spray_density = percent.min(100);
+    }
+
+    pub fn spray_density(&self) -> u8 {
+        self.spray_density
+    }
+
+    /// Whether the spawn mode and spray density let a brush write a cell.
+    pub(crate) fn admits(&mut self, index: usize) -> bool {
+        self.spawn_mode.admits(self.particles[index].p_type)
+            && (self.spray_density >= 100 || self.brush_rng.gen_range(0, 100) < self.spray_density)
+    }
+
+    /// Starts a stroke painting `p_type` with a brush of `radius`.
+    pub fn begin_stroke(&mut self, p_type: ParticleType, radius: usize) {
+        self.end_stroke();
+        self.begin_transaction();
+        self.stroke = Some(Stroke {
+            p_type,
+            radius,
+            last: None,
+        });
+    }
+
+    /// Paints from the stroke's last point to `x`, `y`, without gaps however
+    /// far apart they are. `pressure`, from 0 to 1, scales the brush radius,
+    /// which changes smoothly along the segment. Does nothing outside a
+    /// stroke.
+    pub fn stroke_to(&mut self, x: isize, y: isize, pressure: Option<f32>) {
+        let stroke = match &mut self.stroke {
+            Some(stroke) => stroke,
+            None => return,
+        };
+
+        let p_type = stroke.p_type;
+        let radius = match pressure {
+            Some(pressure) => (stroke.radius as f32 * pressure.clamp(0.0, 1.0)).round() as usize,
+            None => stroke.radius,
+        };
+        let (from, from_radius) = stroke.last.unwrap_or(((x, y), radius));
+        stroke.last = Some(((x, y), radius));
+
+        let mut cells = Vec::new();
+        self.trace(from, (x, y), from_radius, radius, &mut cells);
+        self.paint(cells, p_type);
+    }
+
+    pub fn end_stroke(&mut self) {
+        if self.stroke.take().is_some() {
+            self.end_transaction();
+        }
+    }
+
+    pub fn is_stroking(&self) -> bool {
+        self.stroke.is_some()
+    }
+
+    /// Fills every cell within `radius` of `x`, `y`.
+    pub fn spawn_circle(&mut self, x: isize, y: isize, radius: usize, p_type: ParticleType) {
+        let mut cells = Vec::new();
+        self.disc(x, y, radius.min(isize::MAX as usize) as isize, &mut cells);
+        self.paint(cells, p_type);
+    }
+
+    /// Fills the `width` by `height` rectangle with its top left corner at
+    /// `x`, `y`.
+    pub fn spawn_rect(
+        &mut self,
+        x: isize,
+        y: isize,
+        width: usize,
+        height: usize,
+        p_type: ParticleType,
+    ) {
+        let mut cells = Vec::new();
+
+        for cy in clip(y, height, self.height) {
+            for cx in clip(x, width, self.width) {
+                self.push_cell(cx, cy, &mut cells);
+            }
+        }
+
+        self.paint(cells, p_type);
+    }
+
+    /// Draws a line from `x0`, `y0` to `x1`, `y1`, filling every cell within
+    /// `radius` of it.
+    pub fn spawn_line(
+        &mut self,
+        (x0, y0): (isize, isize),
+        (x1, y1): (isize, isize),
+        radius: usize,
+        p_type: ParticleType,
+    ) {
+        let mut cells = Vec::new();
+        self.trace((x0, y0), (x1, y1), radius, radius, &mut cells);
+        self.paint(cells, p_type);
+    }
+
+    /// Fills the polygon with the given corners, which may be in either
+    /// winding order. Cell `x`, `y` spans `x..x + 1` and `y..y + 1`, and is
+    /// filled if its center is inside by the even-odd rule.
+    pub fn spawn_polygon(&mut self, points: &[(isize, isize)], p_type: ParticleType) {
+        if points.len() < 3 {
+            return;
+        }
+
+        let top = points.iter().map(|p| p.1).min().unwrap().max(0);
+        let bottom = points
+            .iter()
+            .map(|p| p.1)
+            .max()
+            .unwrap()
+            .min(self.height as isize);
+
+        let mut cells = Vec::new();
+        let mut crossings = Vec::new();
+
+        for y in top..bottom {
+            let center = y as f32 + 0.5;
+
+            crossings.clear();
+            for (i, &(ax, ay)) in points.iter().enumerate() {
+                let (bx, by) = points[(i + 1) % points.len()];
+                let (ay, by) = (ay as f32, by as f32);
+
+                if (ay <= center) != (by <= center) {
+                    let t = (center - ay) / (by - ay);
+                    // widened so the difference cannot overflow on wasm32
+                    crossings.push(ax as f32 + t * (bx as i64 - ax as i64) as f32);
+                }
+            }
+            crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            for span in crossings.chunks_exact(2) {
+                let start = (span[0] - 0.5).ceil() as isize;
+                let end = (span[1] - 0.5).ceil() as isize;
+
+                for x in start.max(0)..end.min(self.width as isize) {
+                    self.push_cell(x, y, &mut cells);
+                }
+            }
+        }
+
+        self.paint(cells, p_type);
+    }
+
+    /// Stamps a disc at every cell of a Bresenham line, growing the radius
+    /// evenly from `from_radius` to `to_radius`. Only the part of the line
+    /// whose discs can reach the world is walked.
+    fn trace(
+        &self,
+        from: (isize, isize),
+        to: (isize, isize),
+        from_radius: usize,
+        to_radius: usize,
+        cells: &mut Vec<usize>,
+    ) {
+        let reach = from_radius.max(to_radius) as f64;
+        let (t0, t1) = match self.clip_segment(from, to, reach) {
+            Some(range) => range,
+            None => return,
+        };
+
+        let point = |t: f64| {
+            let along = |a: isize, b: isize| (a as f64 + (b as f64 - a as f64) * t).round() as i64;
+            (along(from.0, to.0), along(from.1, to.1))
+        };
+        let radius_at = |t: f64| from_radius as f64 + (to_radius as f64 - from_radius as f64) * t;
+
+        let ((x0, y0), (x1, y1)) = (point(t0), point(t1));
+        let (from_radius, to_radius) = (radius_at(t0), radius_at(t1));
+
+        // Widened so the differences cannot overflow a 32-bit isize.
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+        let length = dx.max(-dy).max(1) as f64;
+        let mut walked = 0;
+
+        loop {
+            let t = walked as f64 / length;
+            let radius = from_radius + (to_radius - from_radius) * t;
+            self.disc(x as isize, y as isize, radius.round() as isize, cells);
+
+            if (x, y) == (x1, y1) {
+                break;
+            }
+
+            let e2 = 2 * error;
+            if e2 >= dy {
+                error += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                error += dx;
+                y += sy;
+            }
+            walked += 1;
+        }
+    }
+
+    /// The stretch of the segment from `from` to `to`, as fractions of its
+    /// length, that lies within `reach` cells of the world, or nothing if it
+    /// stays further away.
+    fn clip_segment(
+        &self,
+        from: (isize, isize),
+        to: (isize, isize),
+        reach: f64,
+    ) -> Option<(f64, f64)> {
+        let (x, y) = (from.0 as f64, from.1 as f64);
+        let (dx, dy) = (to.0 as f64 - x, to.1 as f64 - y);
+        let (right, bottom) = (
+            self.width as f64 - 1.0 + reach,
+            self.height as f64 - 1.0 + reach,
+        );
+        let (mut t0, mut t1) = (0.0, 1.0);
+
+        // Liang-Barsky: each edge is a direction and the room left before it.
+        for &(direction, room) in [
+            (-dx, x + reach),
+            (dx, right - x),
+            (-dy, y + reach),
+            (dy, bottom - y),
+        ]
+        .iter()
+        {
+            if direction == 0.0 {
+                if room < 0.0 {
+                    return None;
+                }
+            } else if direction < 0.0 {
+                t0 = f64::max(t0, room / direction);
+            } else {
+                t1 = f64::min(t1, room / direction);
+            }
+        }
+
+        Some((t0, t1)).filter(|&(t0, t1)| t0 <= t1)
+    }
+
+    fn disc(&self, x: isize, y: isize, radius: isize, cells: &mut Vec<usize>) {
+        let rows = y.saturating_sub(radius)..y.saturating_add(radius).saturating_add(1);
+
+        for cy in rows.start.max(0)..rows.end.min(self.height as isize) {
+            // Widened so the squares cannot overflow for huge radii.
+            let reach = (radius as i128).pow(2) - ((cy - y) as i128).pow(2);
+            let mut half = (reach as f64).sqrt() as isize;
+            while (half as i128 + 1).pow(2) <= reach {
+                half += 1;
+            }
+            while (half as i128).pow(2) > reach {
+                half -= 1;
+            }
+
+            let columns = x.saturating_sub(half)..x.saturating_add(half).saturating_add(1);
+            for cx in columns.start.max(0)..columns.end.min(self.width as isize) {
+                self.push_cell(cx, cy, cells);
+            }
+        }
+    }
+
+    /// Adds a cell to a brush unless it lies outside the world or on the wall
+    /// border.
+    pub(crate) fn push_cell(&self, x: isize, y: isize, cells: &mut Vec<usize>) {
+        if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
+            return;
+        }
+
+        let (x, y) = (x as usize, y as usize);
+        let index = self.get_index(x, y);
+
+        if is_border(x, y, self.width, self.height)
+            && self.particles[index].p_type == ParticleType::Wall
+        {
+            return;
+        }
+
+        cells.push(index);
+    }
+
+    /// Writes the cells of a brush that the spawn mode and spray density
+    /// admit, each once, as one undo step.
+    fn paint(&mut self, mut cells: Vec<usize>, p_type: ParticleType) {
+        cells.sort_unstable();
+        cells.dedup();
+        cells.retain(|&index| self.admits(index));
+
+        self.paint_each(cells.into_iter().map(|index| (index, p_type)));
+    }
+
+    /// Writes each unprotected cell with its own material as one undo step,
+    /// regardless of the spawn mode.
+    pub(crate) fn paint_each<I>(&mut self, cells: I)
+    where
+        I: IntoIterator<Item = (usize, ParticleType)>,
+    {
+        self.begin_transaction();
+        for (index, p_type) in cells {
+            if !self.protected[index] {
+                self.edit_cell(index, p_type);
+            }
+        }
+        self.end_transaction();
+    }
+}
@@ -0,0 +1,181 @@
+//! Sleep tracking for square chunks of the grid.
+//!
+//! Only awake chunks are simulated. A chunk stays awake for the next tick when
+//! one of its cells changes, when a cell just outside its border changes, or
+//! when one of its particles asks to stay awake. Edits between steps wake the
+//! chunks they touch right away.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+pub const CHUNK_SIZE: usize = 32;
+
+#[derive(Clone, Copy)]
+struct Layout {
+    width: usize,
+    height: usize,
+    columns: usize,
+    rows: usize,
+    wrap: bool,
+}
+
+pub(crate) struct Chunks {
+    layout: Layout,
+    awake: Vec<bool>,
+    next_awake: Vec<AtomicBool>,
+}
+
+impl Layout {
+    /// Calls `f` with every chunk holding a cell within one step of `x`, `y`.
+    fn for_each_around<F: FnMut(usize)>(&self, x: usize, y: usize, mut f: F) {
+        let columns = self.chunks_around(x, self.width, self.columns);
+        let rows = self.chunks_around(y, self.height, self.rows);
+
+        for &r in rows.iter().flatten() {
+            for &c in columns.iter().flatten() {
+                f(r * self.columns + c);
+            }
+        }
+    }
+
+    /// The chunks along one axis holding `position - 1` through
+    /// `position + 1`, with duplicates removed.
+    fn chunks_around(&self, position: usize, size: usize, count: usize) -> [Option<usize>; 3] {
+        let chunk = position / CHUNK_SIZE;
+        let mut before = position.checked_sub(1).map(|p| p / CHUNK_SIZE);
+        let mut after = Some(position + 1)
+            .filter(|&p| p < size)
+            .map(|p| p / CHUNK_SIZE);
+
+        if self.wrap {
+            before = before.or(Some(count - 1));
+            after = after.or(Some(0));
+        }
+
+        let before = before.filter(|&c| c != chunk);
+        let after = after.filter(|&c| c != chunk && Some(c) != before);
+
+        [before, Some(chunk), after]
+    }
+}
+
+impl Chunks {
+    pub(crate) fn new(width: usize, height: usize) -> Chunks {
+        let columns = width.div_ceil(CHUNK_SIZE);
+        let rows = height.div_ceil(CHUNK_SIZE);
+
+        Chunks {
+            layout: Layout {
+                width,
+                height,
+                columns,
+                rows,
+                wrap: false,
+            },
+            awake: vec![true; columns * rows],
+            next_awake: (0..columns * rows)
+                .map(|_| AtomicBool::new(false))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn columns(&self) -> usize {
+        self.layout.columns
+    }
+
+    pub(crate) fn rows(&self) -> usize {
+        self.layout.rows
+    }
+
+    /// Whether cells on opposite edges of the world are neighbors.
+    pub(crate) fn set_wrap(&mut self, wrap: bool) {
+        self.layout.wrap = wrap;
+    }
+
+    pub(crate) fn is_awake(&self, column: usize, row: usize) -> bool {
+        self.awake[row * self.layout.columns + column]
+    }
+
+    pub(crate) fn awake_count(&self) -> usize {
+        self.awake.iter().filter(|&&awake| awake).count()
+    }
+
+    pub(crate) fn awake(&self) -> &[bool] {
+        &self.awake
+    }
+
+    /// Restores the awake chunks, as returned by `awake`.
+    pub(crate) fn set_awake(&mut self, awake: Vec<bool>) {
+        assert_eq!(awake.len(), self.awake.len());
+        self.awake = awake;
+    }
+
+    /// The chunks awake this tick or kept awake for the next one, and every
+    /// chunk next to one, which is as far as heat can spread in one tick.
+    pub(crate) fn awake_and_around(&self) -> Vec<bool> {
+        let Layout {
+            columns,
+            rows,
+            wrap,
+            ..
+        } = self.layout;
+        let mut around = vec![false; columns * rows];
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let index = row * columns + column;
+                if !(self.awake[index] || self.next_awake[index].load(Ordering::Relaxed)) {
+                    continue;
+                }
+
+                for dr in -1..=1 {
+                    for dc in -1..=1 {
+                        let (mut r, mut c) = (row as isize + dr, column as isize + dc);
+                        if wrap {
+                            r = r.rem_euclid(rows as isize);
+                            c = c.rem_euclid(columns as isize);
+                        } else if r < 0 || c < 0 || r >= rows as isize || c >= columns as isize {
+                            continue;
+                        }
+                        around[r as usize * columns + c as usize] = true;
+                    }
+                }
+            }
+        }
+
+        around
+    }
+
+    /// Keeps a single chunk, by its index in `awake`, awake next tick.
+    pub(crate) fn keep_chunk_awake(&self, index: usize) {
+        self.next_awake[index].store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn wake_all(&mut self) {
+        self.awake.iter_mut().for_each(|awake| *awake = true);
+    }
+
+    /// Wakes the chunks around a cell edited between steps.
+    pub(crate) fn wake(&mut self, x: usize, y: usize) {
+        let awake = &mut self.awake;
+        self.layout
+            .for_each_around(x, y, |index| awake[index] = true);
+    }
+
+    /// Keeps the chunks around a cell changed during a step awake next tick.
+    /// Safe to call from several threads at once.
+    pub(crate) fn keep_awake(&self, x: usize, y: usize) {
+        let next_awake = &self.next_awake;
+        self.layout.for_each_around(x, y, |index| {
+            next_awake[index].store(true, Ordering::Relaxed)
+        });
+    }
+
+    /// Moves on to the next tick, putting every chunk that saw no change to
+    /// sleep.
+    pub(crate) fn swap(&mut self) {
+        for (awake, next_awake) in self.awake.iter_mut().zip(self.next_awake.iter()) {
+            *awake = next_awake.swap(false, Ordering::Relaxed);
+        }
+    }
+}
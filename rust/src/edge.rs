@@ -0,0 +1,62 @@
+//! What happens to particles at the edges of the world.
+//!
+//! In wall mode the outermost cells hold a protected wall border. The other
+//! modes let particles reach the edges, so switching to them removes that
+//! border, and switching back paints it again over whatever is there.
+
+use crate::particle::color_seed;
+use crate::particle::Particle;
+use crate::particle::ParticleType;
+use crate::simulation::is_border;
+use crate::simulation::Simulation;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeMode {
+    /// The world is enclosed by solid boundaries.
+    Wall = 0,
+    /// Particles leaving the world are deleted.
+    Void = 1,
+    /// Particles leaving one side come back in on the opposite side.
+    Wrap = 2,
+}
+
+impl EdgeMode {
+    pub fn from_u8(value: u8) -> Option<EdgeMode> {
+        match value {
+            0 => Some(EdgeMode::Wall),
+            1 => Some(EdgeMode::Void),
+            2 => Some(EdgeMode::Wrap),
+            _ => None,
+        }
+    }
+}
+
+impl Simulation {
+    /// Paints the protected wall border, or removes its walls and protection.
+    pub(crate) fn set_border(&mut self, walls: bool) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if !is_border(x, y, self.width, self.height) {
+                    continue;
+                }
+
+                let index = self.get_index(x, y);
+                let before = self.particles[index].p_type;
+                self.protected[index] = walls;
+
+                let after = match (walls, before) {
+                    (true, _) => ParticleType::Wall,
+                    (false, ParticleType::Wall) => ParticleType::Empty,
+                    (false, _) => continue,
+                };
+
+                self.counts.replace(before, after);
+                self.particles[index] = Particle::new(after, color_seed(index, self.tick));
+                self.temperatures[index] = after.material().temperature;
+            }
+        }
+
+        self.chunks.wake_all();
+    }
+}
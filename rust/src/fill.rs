@@ -0,0 +1,206 @@
+//! Whole-region edits: filling a rectangle with a pattern, flood filling a
+//! connected area and clearing the world, each as a single undo step.
+//!
+//! Random patterns draw from the brush rng, which is seeded along with the
+//! simulation and saved with its state, so a fill is reproducible without
+//! changing how the world evolves after it, and two fills in the same tick
+//! still differ.
+
+use std::collections::VecDeque;
+
+use rand::Rng;
+
+use crate::brush::clip;
+use crate::particle::ParticleType;
+use crate::simulation::is_border;
+use crate::simulation::Simulation;
+
+/// How `fill_region` lays out materials.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern {
+    /// Every cell gets the fill material.
+    Solid,
+    /// Each cell gets one of these materials with a chance of its weight in
+    /// percent, and the fill material otherwise. Weights adding up to more
+    /// than 100 are taken relative to their total.
+    Mix(Vec<(ParticleType, u32)>),
+    /// Alternates the fill material with this one, as on a chessboard.
+    Checkerboard(ParticleType),
+}
+
+/// Which neighbors a flood fill spreads to.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Only across edges, so a diagonal line of walls holds the fill back.
+    Four = 4,
+    /// Across edges and corners.
+    Eight = 8,
+}
+
+impl Connectivity {
+    pub fn from_u8(value: u8) -> Option<Connectivity> {
+        match value {
+            4 => Some(Connectivity::Four),
+            8 => Some(Connectivity::Eight),
+            _ => None,
+        }
+    }
+
+    fn offsets(self) -> &'static [(isize, isize)] {
+        const FOUR: [(isize, isize); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+        const EIGHT: [(isize, isize); 8] = [
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (-1, 0),
+            (1, 0),
+            (-1, 1),
+            (0, 1),
+            (1, 1),
+        ];
+
+        match self {
+            Connectivity::Four => &FOUR,
+            Connectivity::Eight => &EIGHT,
+        }
+    }
+}
+
+impl Simulation {
+    /// Fills the `width` by `height` rectangle with its top left corner at
+    /// `x`, `y` with `p_type` laid out in `pattern`.
+    pub fn fill_region(
+        &mut self,
+        x: isize,
+        y: isize,
+        width: usize,
+        height: usize,
+        p_type: ParticleType,
+        pattern: &Pattern,
+    ) {
+        let mut cells = Vec::new();
+        for cy in clip(y, height, self.height) {
+            for cx in clip(x, width, self.width) {
+                self.push_cell(cx, cy, &mut cells);
+            }
+        }
+
+        let rng = &mut self.brush_rng;
+        let total = match pattern {
+            Pattern::Mix(mix) => mix.iter().map(|&(_, weight)| weight).sum::<u32>().max(100),
+            _ => 0,
+        };
+        let columns = self.width;
+
+        let cells: Vec<(usize, ParticleType)> = cells
+            .into_iter()
+            .map(|index| {
+                let p_type = match pattern {
+                    Pattern::Solid => p_type,
+                    Pattern::Mix(mix) => {
+                        let mut roll = rng.gen_range(0, total);
+                        mix.iter()
+                            .find(|&&(_, weight)| {
+                                if roll < weight {
+                                    return true;
+                                }
+                                roll -= weight;
+                                false
+                            })
+                            .map_or(p_type, |&(other, _)| other)
+                    }
+                    Pattern::Checkerboard(other) => {
+                        if (index % columns + index / columns).is_multiple_of(2) {
+                            p_type
+                        } else {
+                            *other
+                        }
+                    }
+                };
+
+                (index, p_type)
+            })
+            .collect();
+
+        self.paint_each(cells);
+    }
+
+    /// Empties the world apart from its wall border and, if `keep_walls` is
+    /// set, any other walls.
+    pub fn clear(&mut self, keep_walls: bool) {
+        let mut cells = Vec::new();
+        for y in 0..self.height as isize {
+            for x in 0..self.width as isize {
+                self.push_cell(x, y, &mut cells);
+            }
+        }
+
+        if keep_walls {
+            cells.retain(|&index| self.particles[index].p_type != ParticleType::Wall);
+        }
+
+        self.paint_each(cells.into_iter().map(|index| (index, ParticleType::Empty)));
+    }
+
+    /// Replaces the area of cells connected to `x`, `y` that hold the same
+    /// material as it with `p_type`, spreading out from `x`, `y` until
+    /// `max_cells` have been filled, if given. The wall border is never
+    /// filled, and protected cells stop the fill like walls. Returns the
+    /// number of cells filled.
+    pub fn flood_fill(
+        &mut self,
+        x: usize,
+        y: usize,
+        p_type: ParticleType,
+        connectivity: Connectivity,
+        max_cells: Option<usize>,
+    ) -> usize {
+        let (width, height) = (self.width, self.height);
+        if x >= width || y >= height {
+            return 0;
+        }
+
+        let target = self.get(x, y);
+        let max_cells = max_cells.unwrap_or(usize::MAX);
+        let protected = &self.protected;
+        let fillable = |x: usize, y: usize| {
+            let border_wall = target == ParticleType::Wall && is_border(x, y, width, height);
+            !(protected[y * width + x] || border_wall)
+        };
+        if target == p_type || max_cells == 0 || !fillable(x, y) {
+            return 0;
+        }
+
+        let mut seen = vec![false; width * height];
+        let mut queue = VecDeque::new();
+        let mut cells = Vec::new();
+        seen[self.get_index(x, y)] = true;
+        queue.push_back((x, y));
+
+        while let Some((x, y)) = queue.pop_front() {
+            cells.push(self.get_index(x, y));
+            if cells.len() == max_cells {
+                break;
+            }
+
+            for &(dx, dy) in connectivity.offsets() {
+                let (nx, ny) = (x as isize + dx, y as isize + dy);
+                if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+                    continue;
+                }
+
+                let (nx, ny) = (nx as usize, ny as usize);
+                let index = self.get_index(nx, ny);
+                if !seen[index] && self.particles[index].p_type == target && fillable(nx, ny) {
+                    seen[index] = true;
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+
+        let filled = cells.len();
+        self.paint_each(cells.into_iter().map(|index| (index, p_type)));
+        filled
+    }
+}
@@ -0,0 +1,222 @@
+//! Per-cell temperature, in degrees Celsius.
+//!
+//! Every step, after the particles have moved, heat flows between each pair
+//! of neighboring cells in proportion to their temperature difference and the
+//! geometric mean of their conductivities, so an insulator blocks heat
+//! entirely. Cells are updated from the previous tick's temperatures, so the
+//! result does not depend on scan order. Empty cells also lose heat to the
+//! surroundings, and heat-emitting materials hold their cells at a fixed
+//! temperature. Cells that cross one of their material's transition
+//! temperatures then change phase.
+//!
+//! Heat only moves within awake chunks and the chunks next to them. A chunk
+//! whose temperatures are still changing is kept awake, so heat spreads into
+//! sleeping parts of the world and settled worlds cost nothing to heat.
+//!
+//! Temperatures belong to cells but are swapped along with particles when
+//! they move, so heat travels with the matter holding it.
+
+use std::ops::Range;
+
+use crate::chunks::CHUNK_SIZE;
+use crate::edge::EdgeMode;
+use crate::particle::color_seed;
+use crate::particle::Particle;
+use crate::particle::ParticleType;
+use crate::recording::Edit;
+use crate::simulation::Simulation;
+
+pub const AMBIENT_TEMPERATURE: f32 = 20.0;
+pub const MIN_TEMPERATURE: f32 = -273.15;
+pub const MAX_TEMPERATURE: f32 = 10_000.0;
+
+/// Share of the difference to ambient temperature empty cells lose per tick.
+const AIR_COOLING: f32 = 0.02;
+/// Largest change in a cell's temperature over a tick that still lets its
+/// chunk fall asleep.
+const SETTLED: f32 = 0.01;
+
+/// Temperatures of freshly created particles, each at its material's default.
+pub(crate) fn initial_temperatures(particles: &[Particle]) -> Vec<f32> {
+    particles
+        .iter()
+        .map(|p| p.p_type.material().temperature)
+        .collect()
+}
+
+impl Simulation {
+    pub fn temperature(&self, x: usize, y: usize) -> f32 {
+        self.temperatures[self.get_index(x, y)]
+    }
+
+    pub fn temperatures(&self) -> &[f32] {
+        &self.temperatures
+    }
+
+    /// Adds `amount` degrees, or removes them if negative, to every cell
+    /// within `radius` of `x`, `y`. Heat is recorded for replay but is not
+    /// undoable.
+    pub fn paint_heat(&mut self, x: usize, y: usize, radius: usize, amount: f32) {
+        self.record(Edit::Heat {
+            x,
+            y,
+            radius,
+            amount,
+        });
+
+        let r = radius as isize;
+        for dy in -r..=r {
+            for dx in -r..=r {
+                if dx * dx + dy * dy > r * r {
+                    continue;
+                }
+
+                let (cx, cy) = (x as isize + dx, y as isize + dy);
+                if cx < 0 || cy < 0 || cx >= self.width as isize || cy >= self.height as isize {
+                    continue;
+                }
+
+                let index = self.get_index(cx as usize, cy as usize);
+                self.chunks.wake(cx as usize, cy as usize);
+                self.temperatures[index] =
+                    (self.temperatures[index] + amount).clamp(MIN_TEMPERATURE, MAX_TEMPERATURE);
+            }
+        }
+    }
+
+    pub(crate) fn update_heat(&mut self) {
+        let region = self.heat_region();
+        if region.is_empty() {
+            return;
+        }
+
+        self.diffuse_heat(&region);
+        self.change_phases(&region);
+    }
+
+    /// The cell ranges of every chunk heat can reach this tick: the awake
+    /// chunks and their neighbors.
+    fn heat_region(&self) -> Vec<(Range<usize>, Range<usize>)> {
+        let columns = self.chunks.columns();
+
+        self.chunks
+            .awake_and_around()
+            .iter()
+            .enumerate()
+            .filter(|(_, &inside)| inside)
+            .map(|(index, _)| {
+                let (x, y) = (index % columns * CHUNK_SIZE, index / columns * CHUNK_SIZE);
+                (
+                    x..(x + CHUNK_SIZE).min(self.width),
+                    y..(y + CHUNK_SIZE).min(self.height),
+                )
+            })
+            .collect()
+    }
+
+    fn diffuse_heat(&mut self, region: &[(Range<usize>, Range<usize>)]) {
+        let (width, height) = (self.width, self.height);
+        let wrap = self.edge_mode == EdgeMode::Wrap;
+        let columns = self.chunks.columns();
+        let chunk_of = |x: usize, y: usize| y / CHUNK_SIZE * columns + x / CHUNK_SIZE;
+
+        let mut inside = vec![false; columns * self.chunks.rows()];
+        for (xs, ys) in region {
+            inside[chunk_of(xs.start, ys.start)] = true;
+        }
+
+        let temperatures = &self.temperatures;
+        let flow = &mut self.heat_flow;
+        flow.resize(width * height, 0.0);
+
+        for (xs, ys) in region {
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    let index = y * width + x;
+                    let conductivity = self.particles[index].p_type.material().conductivity;
+
+                    // Each pair is visited once, from its left or upper cell,
+                    // and only when heat can reach both of its cells.
+                    let right = match x + 1 {
+                        nx if nx < width => Some((nx, y)),
+                        _ if wrap => Some((0, y)),
+                        _ => None,
+                    };
+                    let down = match y + 1 {
+                        ny if ny < height => Some((x, ny)),
+                        _ if wrap => Some((x, 0)),
+                        _ => None,
+                    };
+
+                    for &(nx, ny) in [right, down].iter().flatten() {
+                        if !inside[chunk_of(nx, ny)] {
+                            continue;
+                        }
+
+                        let neighbor = ny * width + nx;
+                        let rate = (conductivity
+                            * self.particles[neighbor].p_type.material().conductivity)
+                            .sqrt()
+                            * 0.25;
+                        let amount = rate * (temperatures[neighbor] - temperatures[index]);
+
+                        flow[index] += amount;
+                        flow[neighbor] -= amount;
+                    }
+                }
+            }
+        }
+
+        for (xs, ys) in region {
+            let mut changed = false;
+
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    let index = y * width + x;
+                    let material = self.particles[index].p_type.material();
+                    let before = self.temperatures[index];
+                    let mut temperature = before + std::mem::take(&mut self.heat_flow[index]);
+
+                    if material.emits_heat {
+                        temperature = material.temperature;
+                    } else if material.id == ParticleType::Empty {
+                        temperature += (AMBIENT_TEMPERATURE - temperature) * AIR_COOLING;
+                    }
+
+                    changed |= (temperature - before).abs() > SETTLED;
+                    self.temperatures[index] = temperature;
+                }
+            }
+
+            if changed {
+                self.chunks.keep_chunk_awake(chunk_of(xs.start, ys.start));
+            }
+        }
+    }
+
+    fn change_phases(&mut self, region: &[(Range<usize>, Range<usize>)]) {
+        for (xs, ys) in region {
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    let index = y * self.width + x;
+                    if self.reactions_respect_protection && self.protected[index] {
+                        continue;
+                    }
+
+                    let material = self.particles[index].p_type.material();
+                    let temperature = self.temperatures[index];
+
+                    let into = match (material.heated, material.cooled) {
+                        (Some(heated), _) if temperature >= heated.at => heated.into,
+                        (_, Some(cooled)) if temperature <= cooled.at => cooled.into,
+                        _ => continue,
+                    };
+
+                    self.counts.replace(material.id, into);
+                    self.particles[index] = Particle::new(into, color_seed(index, self.tick));
+                    self.chunks.keep_awake(x, y);
+                }
+            }
+        }
+    }
+}
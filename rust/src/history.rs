@@ -0,0 +1,220 @@
+//! Undo and redo of user edits.
+//!
+//! Edits are grouped into transactions. Every cell touched inside a
+//! transaction remembers the type it had before the first touch and after the
+//! last one, so undo and redo only rewrite the cells the user changed and leave
+//! the rest of the simulation alone. Edits made outside an explicit
+//! transaction each form their own.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use crate::particle::ParticleType;
+use crate::simulation::Simulation;
+
+/// Default number of cell changes kept across all undoable transactions.
+pub const DEFAULT_HISTORY_LIMIT: usize = 1 << 20;
+
+struct Change {
+    index: usize,
+    before: ParticleType,
+    after: ParticleType,
+}
+
+#[derive(Default)]
+struct Transaction {
+    changes: Vec<Change>,
+    positions: HashMap<usize, usize>,
+}
+
+pub(crate) struct History {
+    undo: VecDeque<Transaction>,
+    redo: Vec<Transaction>,
+    open: Option<Transaction>,
+    depth: usize,
+    used: usize,
+    limit: usize,
+}
+
+impl Transaction {
+    fn track(&mut self, index: usize, before: ParticleType, after: ParticleType) {
+        match self.positions.get(&index) {
+            Some(&position) => self.changes[position].after = after,
+            None => {
+                self.positions.insert(index, self.changes.len());
+                self.changes.push(Change {
+                    index,
+                    before,
+                    after,
+                });
+            }
+        }
+    }
+}
+
+impl History {
+    pub(crate) fn new() -> History {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            open: None,
+            depth: 0,
+            used: 0,
+            limit: DEFAULT_HISTORY_LIMIT,
+        }
+    }
+
+    pub(crate) fn track(&mut self, index: usize, before: ParticleType, after: ParticleType) {
+        match &mut self.open {
+            Some(transaction) => transaction.track(index, before, after),
+            None => {
+                let mut transaction = Transaction::default();
+                transaction.track(index, before, after);
+                self.commit(transaction);
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.open = None;
+        self.depth = 0;
+        self.used = 0;
+    }
+
+    fn begin(&mut self) {
+        if self.depth == 0 {
+            self.open = Some(Transaction::default());
+        }
+
+        self.depth += 1;
+    }
+
+    fn end(&mut self) {
+        if self.depth == 0 {
+            return;
+        }
+
+        self.depth -= 1;
+
+        if self.depth == 0 {
+            if let Some(transaction) = self.open.take() {
+                self.commit(transaction);
+            }
+        }
+    }
+
+    /// Commits any open transaction so undo and redo never interleave with
+    /// a half-finished edit.
+    fn end_all(&mut self) {
+        while self.depth > 0 {
+            self.end();
+        }
+    }
+
+    fn commit(&mut self, mut transaction: Transaction) {
+        transaction.changes.retain(|c| c.before != c.after);
+        transaction.positions = HashMap::new();
+
+        if transaction.changes.is_empty() {
+            return;
+        }
+
+        self.redo.clear();
+        self.used += transaction.changes.len();
+        self.undo.push_back(transaction);
+        self.trim();
+    }
+
+    /// Drops the oldest transactions until the history fits its limit. A
+    /// single transaction larger than the limit is not kept at all.
+    fn trim(&mut self) {
+        while self.used > self.limit {
+            match self.undo.pop_front() {
+                Some(transaction) => self.used -= transaction.changes.len(),
+                None => break,
+            }
+        }
+    }
+}
+
+impl Simulation {
+    /// Groups all edits until the matching `end_transaction` into one undo
+    /// step. Transactions may nest; only the outermost one is committed.
+    pub fn begin_transaction(&mut self) {
+        self.history.begin();
+    }
+
+    pub fn end_transaction(&mut self) {
+        self.history.end();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.history.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.history.redo.is_empty()
+    }
+
+    /// Restores the cells touched by the most recent transaction, apart from
+    /// any protected since. Ends any stroke or transaction in progress first.
+    /// Returns false if there was nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        self.stroke = None;
+        self.history.end_all();
+
+        let transaction = match self.history.undo.pop_back() {
+            Some(transaction) => transaction,
+            None => return false,
+        };
+
+        for change in transaction.changes.iter().rev() {
+            if !self.protected[change.index] {
+                self.write_cell(change.index, change.before);
+            }
+        }
+
+        self.history.used -= transaction.changes.len();
+        self.history.redo.push(transaction);
+
+        true
+    }
+
+    /// Reapplies the most recently undone transaction, apart from cells
+    /// protected since. Ends any stroke or transaction in progress first.
+    /// Returns false if there was nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        self.stroke = None;
+        self.history.end_all();
+
+        let transaction = match self.history.redo.pop() {
+            Some(transaction) => transaction,
+            None => return false,
+        };
+
+        for change in &transaction.changes {
+            if !self.protected[change.index] {
+                self.write_cell(change.index, change.after);
+            }
+        }
+
+        self.history.used += transaction.changes.len();
+        self.history.undo.push_back(transaction);
+        self.history.trim();
+
+        true
+    }
+
+    /// Sets how many cell changes the history may hold, dropping the oldest
+    /// transactions if it is already over.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history.limit = limit;
+        self.history.trim();
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+}
@@ -0,0 +1,109 @@
+//! PNG import and export of the particle grid using the material palette.
+//!
+//! Export writes one RGB pixel per cell with the base material colors and does
+//! not need WebGL. Import maps every pixel to the material with the nearest
+//! palette color.
+
+use crate::heat::initial_temperatures;
+use crate::particle::color_seed;
+use crate::particle::Particle;
+use crate::particle::ParticleType;
+use crate::simulation::Simulation;
+use crate::state::StateError;
+use crate::stats::MaterialCounts;
+
+fn nearest_particle(rgb: [u8; 3], tolerance: f32) -> Option<ParticleType> {
+    let distance = |p_type: &ParticleType| {
+        p_type
+            .color()
+            .iter()
+            .zip(rgb.iter())
+            .map(|(&a, &b)| (i32::from(a) - i32::from(b)).pow(2))
+            .sum::<i32>()
+    };
+
+    ParticleType::all()
+        .min_by_key(|p_type| distance(p_type))
+        .filter(|p_type| (distance(p_type) as f32).sqrt() <= tolerance)
+}
+
+impl Simulation {
+    pub fn export_png(&self) -> Vec<u8> {
+        let pixels: Vec<u8> = self
+            .particles
+            .iter()
+            .flat_map(|p| p.p_type.color())
+            .collect();
+
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().expect("png header encodes");
+        writer
+            .write_image_data(&pixels)
+            .expect("png image data encodes");
+        writer.finish().expect("png encodes");
+
+        data
+    }
+
+    /// Replaces the grid with a PNG of the same size. `tolerance` is the
+    /// largest RGB distance a pixel may be from its nearest palette color.
+    /// The world is left untouched if the image is rejected.
+    pub fn import_png(&mut self, data: &[u8], tolerance: f32) -> Result<(), StateError> {
+        let mut decoder = png::Decoder::new(data);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+        let mut reader = decoder
+            .read_info()
+            .map_err(|e| StateError::Image(e.to_string()))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buffer)
+            .map_err(|e| StateError::Image(e.to_string()))?;
+
+        let (width, height) = (info.width as usize, info.height as usize);
+        if (width, height) != (self.width, self.height) {
+            return Err(StateError::SizeMismatch {
+                expected: (self.width, self.height),
+                found: (width, height),
+            });
+        }
+
+        let channels = info.color_type.samples();
+        let mut particles = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            let row = &buffer[y * info.line_size..];
+
+            for x in 0..width {
+                let pixel = &row[x * channels..];
+                let rgb = match info.color_type {
+                    png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+                        [pixel[0], pixel[0], pixel[0]]
+                    }
+                    _ => [pixel[0], pixel[1], pixel[2]],
+                };
+
+                let p_type =
+                    nearest_particle(rgb, tolerance).ok_or(StateError::UnmatchedColor { x, y })?;
+                particles.push(Particle::new(
+                    p_type,
+                    color_seed(particles.len(), self.tick),
+                ));
+            }
+        }
+
+        self.particles = particles;
+        self.clocks = vec![self.clock; width * height];
+        self.temperatures = initial_temperatures(&self.particles);
+        self.history.clear();
+        self.counts = MaterialCounts::new(&self.particles);
+        self.chunks.wake_all();
+        self.record_load(self.tick);
+
+        Ok(())
+    }
+}
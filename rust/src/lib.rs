@@ -2,468 +2,542 @@
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+#[cfg(feature = "webgl")]
 macro_rules! log {
     ( $( $t:tt )* ) => {
         web_sys::console::log_1(&format!( $( $t )* ).into());
     }
 }
 
-extern crate web_sys;
+/// Without a console, the arguments are still checked and count as used, but
+/// nothing is formatted.
+#[cfg(not(feature = "webgl"))]
+macro_rules! log {
+    ( $( $t:tt )* ) => {
+        let _ = format_args!( $( $t )* );
+    }
+}
 
+mod brush;
+mod chunks;
+mod edge;
+mod fill;
+mod heat;
+mod history;
+mod image;
+mod material;
+mod motion;
 mod particle;
+mod protection;
+mod recording;
+#[cfg(feature = "webgl")]
 mod renderer;
+mod resize;
+mod rules;
+mod scan;
+mod simulation;
+mod state;
+mod stats;
 mod utils;
+mod world_file;
+
+use std::convert::TryFrom;
 
-use rand::{Rng, SeedableRng};
 use wasm_bindgen::prelude::*;
 
-use particle::Direction;
-use particle::Particle;
-use particle::ParticleType;
+pub use brush::SpawnMode;
+pub use edge::EdgeMode;
+pub use fill::{Connectivity, Pattern};
+pub use heat::{AMBIENT_TEMPERATURE, MAX_TEMPERATURE, MIN_TEMPERATURE};
+pub use material::{Material, Movement, Reaction, Transition, MATERIALS};
+pub use motion::{DEFAULT_GRAVITY, MAX_TRAVEL, VELOCITY_SCALE};
+pub use particle::Particle;
+pub use particle::ParticleType;
+pub use recording::{Edit, Event, Recording};
+#[cfg(feature = "webgl")]
 use renderer::Renderer;
+pub use resize::Anchor;
+pub use scan::ScanOrder;
+pub use simulation::Simulation;
+pub use state::StateError;
 
 #[wasm_bindgen]
 pub struct SandGame {
-    particles: Vec<Particle>,
-    clocks: Vec<u8>,
-    width: usize,
-    height: usize,
+    simulation: Simulation,
+    #[cfg(feature = "webgl")]
     renderer: Renderer,
-    clock: u8,
-    rng: rand_pcg::Lcg64Xsh32,
-    time: f32,
+}
+
+/// Everything known about one cell, as returned by `SandGame::get_particle`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct CellInfo {
+    pub material: u8,
+    pub age: u8,
+    pub seed: u8,
+    pub flags: u8,
+    /// Sixteenths of a cell per tick, with y pointing down.
+    pub velocity_x: i8,
+    pub velocity_y: i8,
+    pub temperature: f32,
+    pub protected: bool,
 }
 
 #[wasm_bindgen]
 impl SandGame {
     pub fn new(width: u32, height: u32) -> SandGame {
-        utils::set_panic_hook();
+        SandGame::new_with_seed(width, height, simulation::DEFAULT_SEED)
+    }
 
-        let width = width as usize;
-        let height = height as usize;
-
-        let mut particles: Vec<Particle> = vec![
-            Particle {
-                p_type: ParticleType::Empty
-            };
-            width * height
-        ];
-        let clocks: Vec<u8> = vec![0; width * height];
-
-        let rng = rand_pcg::Pcg32::seed_from_u64(419);
-
-        for y in 0..height {
-            for x in 0..width {
-                let p_type = match (x % width, y % height, x % (width - 1), y % (height - 1)) {
-                    (0, _, _, _) => ParticleType::Wall,
-                    (_, 0, _, _) => ParticleType::Wall,
-                    (_, _, 0, _) => ParticleType::Wall,
-                    (_, _, _, 0) => ParticleType::Wall,
-                    _ => ParticleType::Empty,
-                };
-
-                let index = y * width + x;
-                particles[index].p_type = p_type;
-            }
-        }
+    pub fn new_with_seed(width: u32, height: u32, seed: u64) -> SandGame {
+        utils::set_panic_hook();
 
-        let renderer = Renderer {
-            context: None,
-            program_info: None,
-            buffers: None,
-        };
+        let simulation = Simulation::with_seed(width as usize, height as usize, seed);
 
         log!("Sand game operational.");
 
         SandGame {
-            particles,
-            clocks,
-            width,
-            height,
-            renderer,
-            clock: 0,
-            rng: rng,
-            time: 0.0,
+            simulation,
+            #[cfg(feature = "webgl")]
+            renderer: Renderer {
+                context: None,
+                program_info: None,
+                buffers: None,
+                pixels: Vec::new(),
+            },
         }
     }
 
     pub fn spawn(&mut self, x: u32, y: u32, p_type: u8) {
-        // how????
-        let p_type = match p_type {
-            0 => ParticleType::Empty,
-            1 => ParticleType::Wall,
-            2 => ParticleType::Sand,
-            3 => ParticleType::Water,
-            4 => ParticleType::Plant,
-            5 => ParticleType::Fire,
-            6 => ParticleType::Oil,
-            _ => ParticleType::Empty,
-        };
+        let p_type = ParticleType::from_u8(p_type).unwrap_or(ParticleType::Empty);
 
-        let index = self.get_index(x as usize, y as usize);
-        self.particles[index].p_type = p_type;
+        self.simulation.spawn(x as usize, y as usize, p_type);
     }
 
-    pub fn step(&mut self) {
-        for x in 0..self.width {
-            for y in 0..self.height {
-                let index = self.get_index(x, y);
-
-                let particle = &self.particles[index];
-
-                if self.clocks[index].wrapping_sub(self.clock) == 1 {
-                    continue;
-                }
-
-                match particle.p_type {
-                    ParticleType::Wall => self.update_wall(x, y),
-                    ParticleType::Sand => self.update_sand(x, y),
-                    ParticleType::Water => self.update_water(x, y),
-                    ParticleType::Plant => self.update_plant(x, y),
-                    ParticleType::Fire => self.update_fire(x, y),
-                    ParticleType::Oil => self.update_oil(x, y),
-                    _ => (),
-                };
-            }
+    /// Number of registered materials; ids run from 0 to `material_count() - 1`.
+    pub fn material_count(&self) -> u8 {
+        MATERIALS.len() as u8
+    }
+
+    pub fn material_name(&self, id: u8) -> Option<String> {
+        ParticleType::from_u8(id).map(|p_type| p_type.material().name.to_string())
+    }
+
+    pub fn spawn_circle(&mut self, x: i32, y: i32, radius: u32, p_type: u8) {
+        if let Some(p_type) = ParticleType::from_u8(p_type) {
+            self.simulation
+                .spawn_circle(x as isize, y as isize, radius as usize, p_type);
         }
+    }
 
-        self.clock = self.clock.wrapping_add(1);
-        self.time += 0.16;
+    pub fn spawn_rect(&mut self, x: i32, y: i32, width: u32, height: u32, p_type: u8) {
+        if let Some(p_type) = ParticleType::from_u8(p_type) {
+            self.simulation.spawn_rect(
+                x as isize,
+                y as isize,
+                width as usize,
+                height as usize,
+                p_type,
+            );
+        }
     }
 
-    pub fn render(&mut self) {
-        let f: &[u8] = unsafe {
-            std::slice::from_raw_parts(
-                self.particles.as_ptr() as *const u8,
-                self.particles.len() * std::mem::size_of::<u8>(),
-            )
+    pub fn spawn_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, radius: u32, p_type: u8) {
+        if let Some(p_type) = ParticleType::from_u8(p_type) {
+            self.simulation.spawn_line(
+                (x0 as isize, y0 as isize),
+                (x1 as isize, y1 as isize),
+                radius as usize,
+                p_type,
+            );
+        }
+    }
+
+    /// Fills a polygon given as a flat list of corner coordinates,
+    /// `[x0, y0, x1, y1, ...]`.
+    pub fn spawn_polygon(&mut self, points: &[i32], p_type: u8) {
+        if let Some(p_type) = ParticleType::from_u8(p_type) {
+            let points: Vec<(isize, isize)> = points
+                .chunks_exact(2)
+                .map(|p| (p[0] as isize, p[1] as isize))
+                .collect();
+            self.simulation.spawn_polygon(&points, p_type);
+        }
+    }
+
+    /// Sets which cells brushes write to: 0 overwrites everything, 1 only
+    /// fills empty cells and 2 only replaces cells of material `source`.
+    pub fn set_spawn_mode(&mut self, spawn_mode: u8, source: u8) {
+        let spawn_mode = match spawn_mode {
+            0 => SpawnMode::Overwrite,
+            1 => SpawnMode::OnlyEmpty,
+            2 => match ParticleType::from_u8(source) {
+                Some(source) => SpawnMode::Replace(source),
+                None => return,
+            },
+            _ => return,
         };
 
-        self.renderer
-            .render(f, self.width as u32, self.height as u32, self.time);
+        self.simulation.set_spawn_mode(spawn_mode);
     }
 
-    pub fn initialize_webgl(&mut self) {
-        self.renderer.setup_webgl();
+    pub fn set_spray_density(&mut self, percent: u8) {
+        self.simulation.set_spray_density(percent);
     }
 
-    pub fn update_viewport(&self, width: u32, height: u32) {
-        self.renderer.set_viewport(width, height);
+    /// Starts a stroke, which is a single undo step until `end_stroke`.
+    pub fn begin_stroke(&mut self, p_type: u8, radius: u32) {
+        if let Some(p_type) = ParticleType::from_u8(p_type) {
+            self.simulation.begin_stroke(p_type, radius as usize);
+        }
     }
-}
 
-impl SandGame {
-    fn get_index(&self, x: usize, y: usize) -> usize {
-        (y * self.width + x) as usize
-    }
-
-    fn update_wall(&mut self, x: usize, y: usize) {
-        let index_current = self.get_index(x, y);
-        self.particles[index_current].p_type = ParticleType::Wall;
-        self.clocks[index_current] = self.clock.wrapping_add(1);
-    }
-
-    fn update_oil(&mut self, x: usize, y: usize) {
-        let index_current = self.get_index(x, y);
-        let index_down = self.get_index(x, y + 1);
-        let index_down_left = self.get_index(x - 1, y + 1);
-        let index_down_right = self.get_index(x + 1, y + 1);
-        let index_left = self.get_index(x - 1, y);
-        let index_right = self.get_index(x + 1, y);
-
-        let particle_down = &self.particles[index_down];
-        let particle_down_left = &self.particles[index_down_left];
-        let particle_down_right = &self.particles[index_down_right];
-        let particle_left = &self.particles[index_left];
-        let particle_right = &self.particles[index_right];
-
-        let r = self.rng.gen_range(0, 2);
-
-        let direction = match (
-            particle_down_left.p_type,
-            particle_down.p_type,
-            particle_down_right.p_type,
-            particle_left.p_type,
-            particle_right.p_type,
+    /// Paints the stroke on to `x`, `y`. `pressure`, from 0 to 1, scales the
+    /// brush radius if given.
+    pub fn stroke_to(&mut self, x: i32, y: i32, pressure: Option<f32>) {
+        self.simulation.stroke_to(x as isize, y as isize, pressure);
+    }
+
+    pub fn end_stroke(&mut self) {
+        self.simulation.end_stroke();
+    }
+
+    pub fn fill_region(&mut self, x: i32, y: i32, width: u32, height: u32, p_type: u8) {
+        self.fill(x, y, width, height, p_type, Pattern::Solid);
+    }
+
+    /// Fills a rectangle with `p_type`, mixing in other materials given as
+    /// `[id, percent, id, percent, ...]`.
+    pub fn fill_region_mix(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        p_type: u8,
+        mix: &[u32],
+    ) {
+        let mix = mix
+            .chunks_exact(2)
+            .filter_map(|m| {
+                let other = ParticleType::from_u8(u8::try_from(m[0]).ok()?)?;
+                Some((other, m[1]))
+            })
+            .collect();
+
+        self.fill(x, y, width, height, p_type, Pattern::Mix(mix));
+    }
+
+    pub fn fill_region_checkerboard(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        p_type: u8,
+        other: u8,
+    ) {
+        if let Some(other) = ParticleType::from_u8(other) {
+            self.fill(x, y, width, height, p_type, Pattern::Checkerboard(other));
+        }
+    }
+
+    /// Replaces the connected area of the material at `x`, `y` with `p_type`,
+    /// spreading to 4 or 8 neighbors per cell. Returns the number of cells
+    /// filled.
+    pub fn flood_fill(
+        &mut self,
+        x: u32,
+        y: u32,
+        p_type: u8,
+        connectivity: u8,
+        max_cells: Option<u32>,
+    ) -> u32 {
+        match (
+            ParticleType::from_u8(p_type),
+            Connectivity::from_u8(connectivity),
         ) {
-            (_, ParticleType::Empty, _, _, _) => Direction::Down,
-            (_, _, _, ParticleType::Empty, ParticleType::Empty) => {
-                if r == 0 {
-                    Direction::Left
-                } else {
-                    Direction::Right
-                }
-            }
-            (_, _, _, ParticleType::Empty, _) => Direction::Left,
-            (_, _, _, _, ParticleType::Empty) => Direction::Right,
-            (ParticleType::Empty, _, ParticleType::Empty, _, _) => {
-                if r == 0 {
-                    Direction::DownLeft
-                } else {
-                    Direction::DownRight
-                }
-            }
-            (ParticleType::Empty, _, _, _, _) => Direction::DownLeft,
-            (_, _, ParticleType::Empty, _, _) => Direction::DownRight,
-            _ => Direction::None,
-        };
+            (Some(p_type), Some(connectivity)) => self.simulation.flood_fill(
+                x as usize,
+                y as usize,
+                p_type,
+                connectivity,
+                max_cells.map(|max| max as usize),
+            ) as u32,
+            _ => 0,
+        }
+    }
 
-        let index_new = match direction {
-            Direction::Down => index_down,
-            Direction::DownLeft => index_down_left,
-            Direction::DownRight => index_down_right,
-            Direction::Left => index_left,
-            Direction::Right => index_right,
-            Direction::None => index_current,
-            _ => index_current,
-        };
+    /// Empties the world, keeping its border and, if `keep_walls` is set, all
+    /// other walls.
+    pub fn clear(&mut self, keep_walls: bool) {
+        self.simulation.clear(keep_walls);
+    }
 
-        if direction == Direction::None {
-            return;
+    pub fn step(&mut self) {
+        self.simulation.step();
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.simulation.reseed(seed);
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        self.simulation.save_state()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        self.simulation
+            .load_state(data)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    pub fn export_world(&self, compress: bool) -> Vec<u8> {
+        self.simulation.export_world(compress)
+    }
+
+    pub fn import_world(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        self.simulation
+            .import_world(data)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    pub fn export_png(&self) -> Vec<u8> {
+        self.simulation.export_png()
+    }
+
+    pub fn import_png(&mut self, data: &[u8], tolerance: f32) -> Result<(), JsValue> {
+        self.simulation
+            .import_png(data, tolerance)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    pub fn start_recording(&mut self) {
+        self.simulation.start_recording();
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Vec<u8>> {
+        self.simulation
+            .stop_recording()
+            .map(|recording| recording.to_bytes())
+    }
+
+    pub fn start_replay(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        Recording::from_bytes(data)
+            .and_then(|recording| self.simulation.start_replay(recording))
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.simulation.is_replaying()
+    }
+
+    pub fn stop_replay(&mut self) {
+        self.simulation.stop_replay();
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.simulation.tick()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.simulation.width() as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.simulation.height() as u32
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32, anchor: u8) {
+        if let Some(anchor) = Anchor::from_u8(anchor) {
+            self.simulation
+                .resize(width as usize, height as usize, anchor);
         }
+    }
 
-        self.particles[index_current].p_type = ParticleType::Empty;
-        self.particles[index_new].p_type = ParticleType::Oil;
-        self.clocks[index_current] = self.clock.wrapping_add(1);
-        self.clocks[index_new] = self.clock.wrapping_add(1);
-    }
-
-    fn update_fire(&mut self, x: usize, y: usize) {
-        let index_current = self.get_index(x, y);
-
-        let index_down = self.get_index(x, y + 1);
-        let index_down_left = self.get_index(x - 1, y + 1);
-        let index_down_right = self.get_index(x + 1, y + 1);
-        let index_left = self.get_index(x - 1, y);
-        let index_right = self.get_index(x + 1, y);
-        let index_up = self.get_index(x, y - 1);
-        let index_up_left = self.get_index(x - 1, y - 1);
-        let index_up_right = self.get_index(x + 1, y - 1);
-
-        for index in [
-            index_down,
-            index_down_left,
-            index_down_right,
-            index_left,
-            index_right,
-            index_up,
-            index_up_left,
-            index_up_right,
-        ]
-        .iter()
-        {
-            let particle = &self.particles[*index];
-
-            if particle.p_type == ParticleType::Plant || particle.p_type == ParticleType::Oil {
-                let r = self.rng.gen_range(0, 11);
-
-                if r < 9 {
-                    self.particles[*index].p_type = ParticleType::Fire;
-                    self.clocks[*index] = self.clock.wrapping_add(1);
-                }
-            }
+    /// Steps chunks in the parallel pass order. On wasm the passes run
+    /// without threads, so stepping is ordered differently but no faster.
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.simulation.set_parallel(parallel);
+    }
+
+    pub fn set_scan_order(&mut self, scan_order: u8) {
+        if let Some(scan_order) = ScanOrder::from_u8(scan_order) {
+            self.simulation.set_scan_order(scan_order);
         }
+    }
 
-        let r = self.rng.gen_range(0, 11);
-        if r > 7 {
-            self.particles[index_current].p_type = ParticleType::Empty;
-            self.clocks[index_current] = self.clock.wrapping_add(1);
+    pub fn set_edge_mode(&mut self, edge_mode: u8) {
+        if let Some(edge_mode) = EdgeMode::from_u8(edge_mode) {
+            self.simulation.set_edge_mode(edge_mode);
         }
     }
 
-    fn update_plant(&mut self, x: usize, y: usize) {
-        let index_current = self.get_index(x, y);
-
-        let index_down = self.get_index(x, y + 1);
-        let index_down_left = self.get_index(x - 1, y + 1);
-        let index_down_right = self.get_index(x + 1, y + 1);
-        let index_left = self.get_index(x - 1, y);
-        let index_right = self.get_index(x + 1, y);
-
-        let index_up = self.get_index(x, y - 1);
-        let index_up_left = self.get_index(x - 1, y - 1);
-        let index_up_right = self.get_index(x + 1, y - 1);
-
-        for index in [
-            index_down,
-            index_down_left,
-            index_down_right,
-            index_left,
-            index_right,
-            index_up,
-            index_up_left,
-            index_up_right,
-        ]
-        .iter()
-        {
-            let particle = &self.particles[*index];
-            if particle.p_type == ParticleType::Water {
-                let r = self.rng.gen_range(0, 11);
-                if r < 7 {
-                    self.particles[*index].p_type = ParticleType::Plant;
-                    self.clocks[*index] = self.clock.wrapping_add(1);
-                }
-            }
+    /// Acceleration in cells per tick per tick, with y pointing down.
+    pub fn set_gravity(&mut self, x: f32, y: f32) {
+        self.simulation.set_gravity(x, y);
+    }
+
+    /// Gravity from an angle in degrees, 0 pointing down and 90 left.
+    pub fn set_gravity_angle(&mut self, degrees: f32, strength: f32) {
+        self.simulation.set_gravity_angle(degrees, strength);
+    }
+
+    pub fn awake_chunks(&self) -> u32 {
+        self.simulation.awake_chunks() as u32
+    }
+
+    /// The temperature of the cell at `x`, `y`, or nothing outside the world.
+    pub fn temperature(&self, x: u32, y: u32) -> Option<f32> {
+        let (x, y) = (x as usize, y as usize);
+        if x >= self.simulation.width() || y >= self.simulation.height() {
+            return None;
         }
 
-        self.particles[index_current].p_type = ParticleType::Plant;
+        Some(self.simulation.temperature(x, y))
     }
 
-    fn update_water(&mut self, x: usize, y: usize) {
-        let index_current = self.get_index(x, y);
-        let index_down = self.get_index(x, y + 1);
-        let index_down_left = self.get_index(x - 1, y + 1);
-        let index_down_right = self.get_index(x + 1, y + 1);
-        let index_left = self.get_index(x - 1, y);
-        let index_right = self.get_index(x + 1, y);
+    /// The particle at `x`, `y` and the state of its cell, or nothing outside
+    /// the world.
+    pub fn get_particle(&self, x: u32, y: u32) -> Option<CellInfo> {
+        let (x, y) = (x as usize, y as usize);
+        if x >= self.simulation.width() || y >= self.simulation.height() {
+            return None;
+        }
 
-        let particle_down = &self.particles[index_down];
-        let particle_down_left = &self.particles[index_down_left];
-        let particle_down_right = &self.particles[index_down_right];
-        let particle_left = &self.particles[index_left];
-        let particle_right = &self.particles[index_right];
+        let particle = self.simulation.particle(x, y);
+        Some(CellInfo {
+            material: particle.p_type.id(),
+            age: particle.age,
+            seed: particle.seed,
+            flags: particle.flags,
+            velocity_x: particle.velocity[0],
+            velocity_y: particle.velocity[1],
+            temperature: self.simulation.temperature(x, y),
+            protected: self.simulation.is_protected(x, y),
+        })
+    }
 
-        let r = self.rng.gen_range(0, 2);
+    /// Number of cells holding each material, indexed by material id.
+    pub fn material_counts(&self) -> Vec<u32> {
+        self.simulation
+            .material_counts()
+            .iter()
+            .map(|&count| count as u32)
+            .collect()
+    }
 
-        let direction = match (
-            particle_down_left.p_type,
-            particle_down.p_type,
-            particle_down_right.p_type,
-            particle_left.p_type,
-            particle_right.p_type,
-        ) {
-            (_, ParticleType::Oil, _, _, _) => Direction::Down,
-            (_, ParticleType::Empty, _, _, _) => Direction::Down,
-            (_, _, _, ParticleType::Empty, ParticleType::Empty) => {
-                if r == 0 {
-                    Direction::Left
-                } else {
-                    Direction::Right
-                }
-            }
-            (_, _, _, ParticleType::Oil, ParticleType::Oil) => {
-                if r == 0 {
-                    Direction::Left
-                } else {
-                    Direction::Right
-                }
-            }
-            (_, _, _, ParticleType::Empty, _) => Direction::Left,
-            (_, _, _, ParticleType::Oil, _) => Direction::Left,
-            (_, _, _, _, ParticleType::Empty) => Direction::Right,
-            (_, _, _, _, ParticleType::Oil) => Direction::Right,
-            (ParticleType::Empty, _, ParticleType::Empty, _, _) => {
-                if r == 0 {
-                    Direction::DownLeft
-                } else {
-                    Direction::DownRight
-                }
-            }
-            (ParticleType::Oil, _, ParticleType::Oil, _, _) => {
-                if r == 0 {
-                    Direction::DownLeft
-                } else {
-                    Direction::DownRight
-                }
-            }
-            (ParticleType::Empty, _, _, _, _) => Direction::DownLeft,
-            (ParticleType::Oil, _, _, _, _) => Direction::DownLeft,
-            (_, _, ParticleType::Empty, _, _) => Direction::DownRight,
-            (_, _, ParticleType::Oil, _, _) => Direction::DownRight,
-            _ => Direction::None,
-        };
+    /// Every cell's temperature, row by row.
+    pub fn temperatures(&self) -> Vec<f32> {
+        self.simulation.temperatures().to_vec()
+    }
 
-        let index_new = match direction {
-            Direction::Down => index_down,
-            Direction::DownLeft => index_down_left,
-            Direction::DownRight => index_down_right,
-            Direction::Left => index_left,
-            Direction::Right => index_right,
-            Direction::None => index_current,
-            _ => index_current,
-        };
+    /// Heats the cells around `x`, `y`, or cools them for a negative amount.
+    pub fn paint_heat(&mut self, x: u32, y: u32, radius: u32, amount: f32) {
+        self.simulation
+            .paint_heat(x as usize, y as usize, radius as usize, amount);
+    }
 
-        if direction == Direction::None {
-            return;
+    /// Whether the cell at `x`, `y` is protected, or nothing outside the
+    /// world.
+    pub fn is_protected(&self, x: u32, y: u32) -> Option<bool> {
+        let (x, y) = (x as usize, y as usize);
+        if x >= self.simulation.width() || y >= self.simulation.height() {
+            return None;
         }
 
-        let type_current = self.particles[index_current].p_type;
-        let type_new = self.particles[index_new].p_type;
+        Some(self.simulation.is_protected(x, y))
+    }
 
-        self.particles[index_current].p_type = type_new;
-        self.particles[index_new].p_type = type_current;
-        self.clocks[index_current] = self.clock.wrapping_add(1);
-        self.clocks[index_new] = self.clock.wrapping_add(1);
+    pub fn set_protected(&mut self, x: u32, y: u32, protected: bool) {
+        self.simulation
+            .set_protected(x as usize, y as usize, protected);
     }
 
-    fn update_sand(&mut self, x: usize, y: usize) {
-        let index_current = self.get_index(x, y);
-        let index_down = self.get_index(x, y + 1);
-        let index_down_left = self.get_index(x - 1, y + 1);
-        let index_down_right = self.get_index(x + 1, y + 1);
+    pub fn protect_rect(&mut self, x: u32, y: u32, width: u32, height: u32, protected: bool) {
+        self.simulation.protect_rect(
+            x as usize,
+            y as usize,
+            width as usize,
+            height as usize,
+            protected,
+        );
+    }
 
-        let particle_down = &self.particles[index_down];
-        let particle_down_left = &self.particles[index_down_left];
-        let particle_down_right = &self.particles[index_down_right];
+    /// The protection mask, one byte per cell, for drawing an editor overlay.
+    pub fn protected_cells(&self) -> Vec<u8> {
+        self.simulation
+            .protected_cells()
+            .iter()
+            .map(|&protected| protected as u8)
+            .collect()
+    }
 
-        let r = self.rng.gen_range(0, 2);
+    pub fn set_reactions_respect_protection(&mut self, respect: bool) {
+        self.simulation.set_reactions_respect_protection(respect);
+    }
 
-        let direction = match (
-            particle_down_left.p_type,
-            particle_down.p_type,
-            particle_down_right.p_type,
-        ) {
-            (_, ParticleType::Empty, _) => Direction::Down,
-            (_, ParticleType::Water, _) => Direction::Down,
-            (_, ParticleType::Oil, _) => Direction::Down,
-            (ParticleType::Empty, _, ParticleType::Empty) => {
-                if r == 0 {
-                    Direction::DownLeft
-                } else {
-                    Direction::DownRight
-                }
-            }
-            (ParticleType::Water, _, ParticleType::Water) => {
-                if r == 0 {
-                    Direction::DownLeft
-                } else {
-                    Direction::DownRight
-                }
-            }
-            (ParticleType::Oil, _, ParticleType::Oil) => {
-                if r == 0 {
-                    Direction::DownLeft
-                } else {
-                    Direction::DownRight
-                }
-            }
-            (ParticleType::Empty, _, _) => Direction::DownLeft,
-            (ParticleType::Water, _, _) => Direction::DownLeft,
-            (ParticleType::Oil, _, _) => Direction::DownLeft,
-            (_, _, ParticleType::Empty) => Direction::DownRight,
-            (_, _, ParticleType::Water) => Direction::DownRight,
-            (_, _, ParticleType::Oil) => Direction::DownRight,
-            _ => Direction::None,
-        };
+    pub fn begin_transaction(&mut self) {
+        self.simulation.begin_transaction();
+    }
 
-        let index_new = match direction {
-            Direction::Down => index_down,
-            Direction::DownLeft => index_down_left,
-            Direction::DownRight => index_down_right,
-            Direction::None => index_current,
-            _ => index_current,
-        };
+    pub fn end_transaction(&mut self) {
+        self.simulation.end_transaction();
+    }
 
-        if direction == Direction::None {
-            return;
-        }
+    pub fn undo(&mut self) -> bool {
+        self.simulation.undo()
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.simulation.redo()
+    }
 
-        let type_current = self.particles[index_current].p_type;
-        let type_new = self.particles[index_new].p_type;
+    pub fn can_undo(&self) -> bool {
+        self.simulation.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.simulation.can_redo()
+    }
 
-        self.particles[index_current].p_type = type_new;
-        self.particles[index_new].p_type = type_current;
-        self.clocks[index_current] = self.clock.wrapping_add(1);
-        self.clocks[index_new] = self.clock.wrapping_add(1);
+    pub fn set_history_limit(&mut self, limit: u32) {
+        self.simulation.set_history_limit(limit as usize);
+    }
+}
+
+#[cfg(feature = "webgl")]
+#[wasm_bindgen]
+impl SandGame {
+    pub fn render(&mut self) {
+        self.renderer.render(
+            self.simulation.particles(),
+            self.simulation.width() as u32,
+            self.simulation.height() as u32,
+            self.simulation.time(),
+        );
+    }
+
+    pub fn initialize_webgl(&mut self) -> Result<(), JsValue> {
+        self.renderer.setup_webgl()
+    }
+
+    pub fn update_viewport(&self, width: u32, height: u32) {
+        self.renderer.set_viewport(width, height);
+    }
+}
+
+impl SandGame {
+    fn fill(&mut self, x: i32, y: i32, width: u32, height: u32, p_type: u8, pattern: Pattern) {
+        if let Some(p_type) = ParticleType::from_u8(p_type) {
+            self.simulation.fill_region(
+                x as isize,
+                y as isize,
+                width as usize,
+                height as usize,
+                p_type,
+                &pattern,
+            );
+        }
     }
 }
@@ -0,0 +1,248 @@
+//! The material registry.
+//!
+//! Every material is a single entry in the `materials!` table below. The entry
+//! decides how the material moves, how it reacts with its neighbors and with
+//! heat, and how it is drawn, so adding a powder or a liquid only means adding
+//! an entry. Ids are stored in saves and must stay stable and in order.
+
+use crate::heat::AMBIENT_TEMPERATURE;
+use crate::particle::ParticleType;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Movement {
+    /// Never moves on its own.
+    Static,
+    /// Falls straight down or slides diagonally.
+    Powder,
+    /// Falls and spreads sideways.
+    Liquid,
+    /// Rises and spreads sideways.
+    Gas,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reaction {
+    None,
+    /// Turns neighboring `feeds_on` particles into itself with `chance`
+    /// percent chance every tick.
+    Grow {
+        feeds_on: ParticleType,
+        chance: u8,
+    },
+}
+
+/// A phase change once a cell's temperature crosses `at`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transition {
+    pub at: f32,
+    pub into: ParticleType,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    pub id: ParticleType,
+    pub name: &'static str,
+    pub movement: Movement,
+    /// Movable particles sink through lighter liquids and gases and rise
+    /// through heavier ones.
+    pub density: u8,
+    pub color: [u8; 3],
+    pub reaction: Reaction,
+    /// Ticks before the particle disappears, or 0 to live forever. A
+    /// particle's color seed shortens this by up to half, so particles
+    /// created together do not all vanish on the same tick.
+    pub lifetime: u8,
+    /// Share of a temperature difference passed to each neighbor per tick,
+    /// from 0 to 1. Two cells exchange heat at the geometric mean of their
+    /// rates.
+    pub conductivity: f32,
+    /// Temperature of cells painted with this material.
+    pub temperature: f32,
+    /// Holds its cell at `temperature` while it exists.
+    pub emits_heat: bool,
+    /// Change at or above a temperature, such as boiling or catching fire.
+    pub heated: Option<Transition>,
+    /// Change at or below a temperature, such as freezing.
+    pub cooled: Option<Transition>,
+}
+
+macro_rules! materials {
+    ( $( $id:literal => $name:ident { $( $field:ident : $value:expr ),* $(,)? } )* ) => {
+        #[allow(non_upper_case_globals)]
+        impl ParticleType {
+            $( pub const $name: ParticleType = ParticleType($id); )*
+        }
+
+        const TABLE: &[Material] = &[
+            $(
+                Material {
+                    id: ParticleType($id),
+                    name: stringify!($name),
+                    $( $field: $value ),*
+                },
+            )*
+        ];
+    };
+}
+
+materials! {
+    0 => Empty {
+        movement: Movement::Static,
+        density: 0,
+        color: [0, 0, 0],
+        reaction: Reaction::None,
+        lifetime: 0,
+        conductivity: 0.05,
+        temperature: AMBIENT_TEMPERATURE,
+        emits_heat: false,
+        heated: None,
+        cooled: None,
+    }
+    1 => Wall {
+        movement: Movement::Static,
+        density: 255,
+        color: [220, 220, 220],
+        reaction: Reaction::None,
+        lifetime: 0,
+        conductivity: 0.0,
+        temperature: AMBIENT_TEMPERATURE,
+        emits_heat: false,
+        heated: None,
+        cooled: None,
+    }
+    2 => Sand {
+        movement: Movement::Powder,
+        density: 160,
+        color: [194, 178, 128],
+        reaction: Reaction::None,
+        lifetime: 0,
+        conductivity: 0.2,
+        temperature: AMBIENT_TEMPERATURE,
+        emits_heat: false,
+        heated: Some(Transition {
+            at: 600.0,
+            into: ParticleType::Glass,
+        }),
+        cooled: None,
+    }
+    3 => Water {
+        movement: Movement::Liquid,
+        density: 100,
+        color: [128, 197, 222],
+        reaction: Reaction::None,
+        lifetime: 0,
+        conductivity: 0.4,
+        temperature: AMBIENT_TEMPERATURE,
+        emits_heat: false,
+        heated: Some(Transition {
+            at: 100.0,
+            into: ParticleType::Steam,
+        }),
+        cooled: Some(Transition {
+            at: 0.0,
+            into: ParticleType::Ice,
+        }),
+    }
+    4 => Plant {
+        movement: Movement::Static,
+        density: 255,
+        color: [50, 205, 50],
+        reaction: Reaction::Grow {
+            feeds_on: ParticleType::Water,
+            chance: 64,
+        },
+        lifetime: 0,
+        conductivity: 0.3,
+        temperature: AMBIENT_TEMPERATURE,
+        emits_heat: false,
+        heated: Some(Transition {
+            at: 150.0,
+            into: ParticleType::Fire,
+        }),
+        cooled: None,
+    }
+    5 => Fire {
+        movement: Movement::Static,
+        density: 255,
+        color: [170, 16, 0],
+        reaction: Reaction::None,
+        lifetime: 6,
+        conductivity: 1.0,
+        temperature: 1000.0,
+        emits_heat: true,
+        heated: None,
+        cooled: None,
+    }
+    6 => Oil {
+        movement: Movement::Liquid,
+        density: 80,
+        color: [55, 58, 54],
+        reaction: Reaction::None,
+        lifetime: 0,
+        conductivity: 0.3,
+        temperature: AMBIENT_TEMPERATURE,
+        emits_heat: false,
+        heated: Some(Transition {
+            at: 200.0,
+            into: ParticleType::Fire,
+        }),
+        cooled: None,
+    }
+    7 => Ice {
+        movement: Movement::Static,
+        density: 255,
+        color: [200, 230, 255],
+        reaction: Reaction::None,
+        lifetime: 0,
+        conductivity: 0.5,
+        temperature: -20.0,
+        emits_heat: false,
+        heated: Some(Transition {
+            at: 1.0,
+            into: ParticleType::Water,
+        }),
+        cooled: None,
+    }
+    8 => Steam {
+        movement: Movement::Gas,
+        density: 1,
+        color: [210, 210, 220],
+        reaction: Reaction::None,
+        lifetime: 0,
+        conductivity: 0.2,
+        temperature: 120.0,
+        emits_heat: false,
+        heated: None,
+        // well below boiling, so steam rises for a while before raining
+        cooled: Some(Transition {
+            at: 50.0,
+            into: ParticleType::Water,
+        }),
+    }
+    9 => Glass {
+        movement: Movement::Static,
+        density: 255,
+        color: [160, 200, 190],
+        reaction: Reaction::None,
+        lifetime: 0,
+        conductivity: 0.2,
+        temperature: AMBIENT_TEMPERATURE,
+        emits_heat: false,
+        heated: None,
+        cooled: None,
+    }
+}
+
+// Lookups index the table by id.
+const _: () = {
+    let mut i = 0;
+    while i < TABLE.len() {
+        assert!(
+            TABLE[i].id.0 as usize == i,
+            "material ids must match their position"
+        );
+        i += 1;
+    }
+};
+
+pub static MATERIALS: &[Material] = TABLE;
@@ -0,0 +1,278 @@
+//! Velocity and gravity for falling particles.
+//!
+//! Gravity is a vector, so it can point in any direction or be switched off.
+//! Powders and liquids speed up while they fall and can cross several cells
+//! in one tick. The path is walked a cell at a time, so nothing tunnels
+//! through an obstacle. A fast particle that lands bounces sideways and passes
+//! part of its momentum on to whatever it hit, so sand scatters and water
+//! splashes.
+//!
+//! Velocities are stored in sixteenths of a cell per tick. The fraction of a
+//! cell left over each tick is travelled or not at random, weighted by its
+//! size.
+
+use rand::Rng;
+
+use crate::material::Movement;
+use crate::recording::Edit;
+use crate::rules::Rules;
+use crate::simulation::Simulation;
+
+/// Velocity units per cell.
+pub const VELOCITY_SCALE: i32 = 16;
+/// Most cells a particle travels in one tick.
+pub const MAX_TRAVEL: i32 = 7;
+/// Furthest from its own cell that a cell update reads or writes. Parallel
+/// steps need this well below `CHUNK_SIZE` to keep their passes apart.
+pub(crate) const REACH: usize = MAX_TRAVEL as usize + 1;
+/// Downward acceleration of new worlds, in cells per tick per tick.
+pub const DEFAULT_GRAVITY: f32 = 0.25;
+
+const MAX_SPEED: i32 = MAX_TRAVEL * VELOCITY_SCALE;
+/// Speed a resting particle loses every tick.
+const FRICTION: i32 = 4;
+
+/// The eight neighbor offsets by angle, clockwise from the right with y
+/// pointing down.
+const OCTANTS: [(isize, isize); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+/// Neighbor offsets relative to gravity, which is snapped to the nearest of
+/// the eight neighbors.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Frame {
+    pub(crate) down: (isize, isize),
+    pub(crate) down_left: (isize, isize),
+    pub(crate) down_right: (isize, isize),
+    pub(crate) left: (isize, isize),
+    pub(crate) right: (isize, isize),
+}
+
+impl Frame {
+    /// The frame for gravity along `gravity`, or `None` in zero gravity.
+    pub(crate) fn new(gravity: [f32; 2]) -> Option<Frame> {
+        let [x, y] = gravity;
+        if x == 0.0 && y == 0.0 {
+            return None;
+        }
+
+        let octant = (y.atan2(x) / std::f32::consts::FRAC_PI_4).round() as isize;
+        let at = |turn: isize| OCTANTS[(octant + turn).rem_euclid(8) as usize];
+
+        Some(Frame {
+            down: at(0),
+            down_left: at(1),
+            down_right: at(-1),
+            left: at(2),
+            right: at(-2),
+        })
+    }
+
+    /// The same frame upside down, for gases rising against gravity.
+    pub(crate) fn flipped(self) -> Frame {
+        let back = |(x, y): (isize, isize)| (-x, -y);
+
+        Frame {
+            down: back(self.down),
+            down_left: back(self.down_right),
+            down_right: back(self.down_left),
+            left: self.left,
+            right: self.right,
+        }
+    }
+
+    /// 1 if a step goes along gravity, 0 if across it and -1 if against it.
+    pub(crate) fn fall(&self, step: (isize, isize)) -> isize {
+        (step.0 * self.down.0 + step.1 * self.down.1).signum()
+    }
+}
+
+impl Simulation {
+    /// Sets the acceleration in cells per tick per tick along x and y, with y
+    /// pointing down. Powders and liquids fall and gases rise along it,
+    /// snapped to the nearest of the eight neighbor directions. At zero
+    /// nothing falls. Gravity that is not finite is ignored.
+    pub fn set_gravity(&mut self, x: f32, y: f32) {
+        if !(x.is_finite() && y.is_finite()) {
+            return;
+        }
+
+        self.record(Edit::Gravity { x, y });
+        self.gravity = [x, y];
+        // settled piles need to find their new floor
+        self.chunks.wake_all();
+    }
+
+    /// Sets gravity from an angle in degrees and a strength, with 0 pointing
+    /// down, 90 left, 180 up and 270 right.
+    pub fn set_gravity_angle(&mut self, degrees: f32, strength: f32) {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        self.set_gravity(-sin * strength, cos * strength);
+    }
+
+    pub fn gravity(&self) -> [f32; 2] {
+        self.gravity
+    }
+
+    /// Gravity in velocity units, as used by the rules, no stronger than the
+    /// top speed so adding it to a velocity cannot overflow.
+    pub(crate) fn gravity_units(&self) -> [i32; 2] {
+        let units = |g: f32| {
+            (g * VELOCITY_SCALE as f32)
+                .round()
+                .clamp(-MAX_SPEED as f32, MAX_SPEED as f32) as i32
+        };
+        [units(self.gravity[0]), units(self.gravity[1])]
+    }
+}
+
+/// `value * i / steps`, rounded half away from zero.
+fn along(value: i32, i: i32, steps: i32) -> isize {
+    ((2 * value * i + steps * value.signum()) / (2 * steps)) as isize
+}
+
+/// Removes the part of `velocity` heading along `direction`.
+fn stop_along(velocity: &mut [i32; 2], direction: (isize, isize)) {
+    let (dx, dy) = (direction.0 as i32, direction.1 as i32);
+    let projection = (velocity[0] * dx + velocity[1] * dy) / (dx * dx + dy * dy);
+
+    if projection > 0 {
+        velocity[0] -= projection * dx;
+        velocity[1] -= projection * dy;
+    }
+}
+
+impl Rules<'_> {
+    /// Accelerates a powder or liquid and moves it along its velocity.
+    /// Returns whether it moved, so the one-cell rules can take over if not.
+    pub(crate) fn fall(&mut self, x: usize, y: usize) -> bool {
+        let index = self.get_index(x, y);
+        let particle = self.cells.get(index);
+        let movement = particle.p_type.material().movement;
+        let mut velocity = [
+            (particle.velocity[0] as i32 + self.gravity[0]).clamp(-MAX_SPEED, MAX_SPEED),
+            (particle.velocity[1] as i32 + self.gravity[1]).clamp(-MAX_SPEED, MAX_SPEED),
+        ];
+
+        let dx = self.travel(velocity[0]);
+        let dy = self.travel(velocity[1]);
+        let steps = dx.abs().max(dy.abs());
+
+        let mut current = index;
+        let (mut ox, mut oy) = (0, 0);
+        let mut hit = None;
+
+        for i in 1..=steps {
+            let (nx, ny) = (along(dx, i, steps), along(dy, i, steps));
+            let next = self.neighbor(x, y, nx, ny);
+            let step = (nx - ox, ny - oy);
+            let fall = self.frame.map_or(0, |frame| frame.fall(step));
+
+            if !self.can_displace(current, next, fall) {
+                hit = Some((next, step));
+                break;
+            }
+
+            self.move_particle(current, next);
+            match next {
+                Some(next) => current = next,
+                // fell into the void
+                None => return true,
+            }
+
+            ox = nx;
+            oy = ny;
+        }
+
+        let frame = self.frame;
+        let supported = match (hit, frame) {
+            (Some((_, step)), Some(frame)) => frame.fall(step) > 0,
+            (None, Some(frame)) if steps == 0 => {
+                let below = self.neighbor(x, y, frame.down.0, frame.down.1);
+                !self.can_displace(index, below, 1)
+            }
+            _ => false,
+        };
+
+        if let Some((target, step)) = hit {
+            let speed = velocity[0].abs().max(velocity[1].abs());
+
+            if speed >= VELOCITY_SCALE {
+                if let Some(target) = target {
+                    self.push(target, velocity[0] / 2, velocity[1] / 2);
+                }
+            }
+
+            stop_along(&mut velocity, step);
+
+            // a hard landing bounces off to one side
+            if let Some(frame) = frame.filter(|_| supported && speed >= VELOCITY_SCALE) {
+                let scatter = match movement {
+                    Movement::Liquid => speed / 2,
+                    _ => speed / 4,
+                };
+                let side = if self.rng.gen_range(0, 2) == 0 {
+                    frame.left
+                } else {
+                    frame.right
+                };
+                velocity[0] += side.0 as i32 * scatter;
+                velocity[1] += side.1 as i32 * scatter;
+            }
+        }
+
+        if let Some(frame) = frame.filter(|_| supported) {
+            stop_along(&mut velocity, frame.down);
+            for v in velocity.iter_mut() {
+                *v -= v.signum() * v.abs().min(FRICTION);
+            }
+        }
+
+        let mut particle = self.cells.get(current);
+        particle.velocity = [
+            velocity[0].clamp(-MAX_SPEED, MAX_SPEED) as i8,
+            velocity[1].clamp(-MAX_SPEED, MAX_SPEED) as i8,
+        ];
+        self.cells.set(current, particle);
+
+        (ox, oy) != (0, 0)
+    }
+
+    /// Whole cells to travel this tick at `velocity`.
+    fn travel(&mut self, velocity: i32) -> i32 {
+        let speed = velocity.abs();
+        let mut cells = speed / VELOCITY_SCALE;
+
+        if self.rng.gen_range(0, VELOCITY_SCALE) < speed % VELOCITY_SCALE {
+            cells += 1;
+        }
+
+        cells.min(MAX_TRAVEL) * velocity.signum()
+    }
+
+    /// Passes momentum on to a movable particle that was hit.
+    fn push(&mut self, index: usize, dvx: i32, dvy: i32) {
+        let mut particle = self.cells.get(index);
+
+        if particle.p_type.material().movement == Movement::Static {
+            return;
+        }
+
+        let [vx, vy] = particle.velocity;
+        particle.velocity = [
+            (vx as i32 + dvx).clamp(-MAX_SPEED, MAX_SPEED) as i8,
+            (vy as i32 + dvy).clamp(-MAX_SPEED, MAX_SPEED) as i8,
+        ];
+        self.cells.set(index, particle);
+        self.chunks
+            .keep_awake(index % self.width, index / self.width);
+    }
+}
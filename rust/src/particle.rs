@@ -1,22 +1,83 @@
+use std::fmt;
+
+use crate::material::Material;
+use crate::material::MATERIALS;
+
 #[repr(C)]
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Particle {
     pub p_type: ParticleType,
+    /// Ticks this particle has been simulated, saturating at 255. Particles in
+    /// sleeping chunks do not age.
+    pub age: u8,
+    /// Shade variation picked when the particle is created.
+    pub seed: u8,
+    /// Bits free for material rules to use. Cleared on creation.
+    pub flags: u8,
+    /// Sixteenths of a cell per tick (`VELOCITY_SCALE` units) along x and y,
+    /// with y pointing down.
+    pub velocity: [i8; 2],
 }
 
-#[repr(u8)]
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq)]
-pub enum ParticleType {
-    Empty = 0,
-    Wall = 1,
-    Sand = 2,
-    Water = 3,
-    Plant = 4,
-    Fire = 5,
-    Oil = 6,
+impl Particle {
+    pub fn new(p_type: ParticleType, seed: u8) -> Particle {
+        Particle {
+            p_type,
+            age: 0,
+            seed,
+            flags: 0,
+            velocity: [0, 0],
+        }
+    }
+}
+
+/// Color seed for a particle created in cell `index`. `salt` varies it over
+/// time so a refilled cell does not always get the same shade.
+pub(crate) fn color_seed(index: usize, salt: u64) -> u8 {
+    // splitmix64 finalizer
+    let mut z = (index as u64 ^ salt.rotate_left(32)).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (z ^ (z >> 31)) as u8
+}
+
+/// A material id. The named constants, such as `ParticleType::Sand`, are
+/// generated from the registry in material.rs.
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Hash)]
+pub struct ParticleType(pub(crate) u8);
+
+impl ParticleType {
+    pub fn all() -> impl Iterator<Item = ParticleType> {
+        MATERIALS.iter().map(|m| m.id)
+    }
+
+    pub fn from_u8(value: u8) -> Option<ParticleType> {
+        MATERIALS.get(value as usize).map(|m| m.id)
+    }
+
+    pub fn id(self) -> u8 {
+        self.0
+    }
+
+    pub fn material(self) -> &'static Material {
+        &MATERIALS[self.0 as usize]
+    }
+
+    /// Base display color, also used by the fragment shader in renderer.rs.
+    pub fn color(self) -> [u8; 3] {
+        self.material().color
+    }
+}
+
+impl fmt::Debug for ParticleType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.material().name)
+    }
 }
 
 #[repr(u8)]
+#[allow(dead_code)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Direction {
     Left = 0,
@@ -0,0 +1,75 @@
+//! Protected cells, which no edit can change.
+//!
+//! Spawning, brushes and bulk edits all skip protected cells, and flood fills
+//! treat them as walls. New worlds start with their wall border protected.
+//! Reactions and phase changes ignore protection unless told otherwise, so by
+//! default a protected plant still burns.
+//!
+//! Protection belongs to the cell, not to the particle in it: whatever moves
+//! into a protected cell is protected while it stays there.
+
+use crate::recording::Edit;
+use crate::simulation::is_border;
+use crate::simulation::Simulation;
+
+/// A mask with only the cells of the wall border set.
+pub(crate) fn border_mask(width: usize, height: usize) -> Vec<bool> {
+    (0..width * height)
+        .map(|index| is_border(index % width, index / width, width, height))
+        .collect()
+}
+
+impl Simulation {
+    pub fn is_protected(&self, x: usize, y: usize) -> bool {
+        self.protected[self.get_index(x, y)]
+    }
+
+    pub fn protected_cells(&self) -> &[bool] {
+        &self.protected
+    }
+
+    pub fn set_protected(&mut self, x: usize, y: usize, protected: bool) {
+        self.protect_rect(x, y, 1, 1, protected);
+    }
+
+    /// Protects or unprotects the `width` by `height` rectangle with its top
+    /// left corner at `x`, `y`, clipped to the world. Protection is recorded
+    /// for replay but is not undoable.
+    pub fn protect_rect(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        protected: bool,
+    ) {
+        self.record(Edit::Protect {
+            x,
+            y,
+            width,
+            height,
+            protected,
+        });
+
+        let right = x.saturating_add(width).min(self.width);
+        let bottom = y.saturating_add(height).min(self.height);
+
+        for cy in y..bottom {
+            for cx in x..right {
+                let index = self.get_index(cx, cy);
+                self.protected[index] = protected;
+            }
+        }
+    }
+
+    /// Sets whether reactions, such as plants growing and heat setting things
+    /// alight, leave protected cells alone.
+    pub fn set_reactions_respect_protection(&mut self, respect: bool) {
+        self.record(Edit::RespectProtection(respect));
+        self.reactions_respect_protection = respect;
+    }
+
+    pub fn reactions_respect_protection(&self) -> bool {
+        self.reactions_respect_protection
+    }
+}
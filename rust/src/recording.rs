@@ -0,0 +1,412 @@
+//! Recording of every edit made to a simulation, for deterministic replay.
+//!
+//! A recording starts with a `save_state` snapshot and lists each edit with
+//! the tick it happened on. Replaying loads the snapshot and applies each edit
+//! once the simulation reaches its tick, so stepping the replaying world
+//! reproduces the original run frame by frame.
+//!
+//! Serialized layout, all integers little-endian:
+//!
+//! | bytes | contents                     |
+//! |-------|------------------------------|
+//! | 4     | magic `SREC`                 |
+//! | 1     | format version               |
+//! | 4     | snapshot length              |
+//! | n     | snapshot                     |
+//! | 4     | event count                  |
+//! | ...   | events                       |
+//!
+//! Each event is its tick as a `u64`, a kind byte and a payload:
+//!
+//! | kind | edit    | payload                                   |
+//! |------|---------|-------------------------------------------|
+//! | 0    | spawn   | x `u32`, y `u32`, particle type `u8`      |
+//! | 1    | reseed  | seed `u64`                                |
+//! | 2    | resize  | width `u32`, height `u32`, anchor `u8`    |
+//! | 3    | heat    | x, y, radius `u32`, amount `f32`          |
+//! | 4    | protect | x, y, width, height `u32`, protected `u8` |
+//! | 5    | gravity | x, y `f32`                                |
+//! | 6    | edges   | edge mode `u8`                            |
+//! | 7    | scan    | scan order `u8`                           |
+//! | 8    | threads | parallel `u8`                             |
+//! | 9    | load    | snapshot length `u32`, snapshot           |
+//! | 10   | respect | reactions respect protection `u8`         |
+//!
+//! Loading a state or importing a world replaces the whole grid, so it is
+//! recorded as a snapshot of the world right after it.
+
+use crate::edge::EdgeMode;
+use crate::particle::ParticleType;
+use crate::resize::Anchor;
+use crate::scan::ScanOrder;
+use crate::simulation::Simulation;
+use crate::state::Reader;
+use crate::state::StateError;
+
+const MAGIC: &[u8; 4] = b"SREC";
+const VERSION: u8 = 1;
+
+const KIND_SPAWN: u8 = 0;
+const KIND_RESEED: u8 = 1;
+const KIND_RESIZE: u8 = 2;
+const KIND_HEAT: u8 = 3;
+const KIND_PROTECT: u8 = 4;
+const KIND_GRAVITY: u8 = 5;
+const KIND_EDGE_MODE: u8 = 6;
+const KIND_SCAN_ORDER: u8 = 7;
+const KIND_PARALLEL: u8 = 8;
+const KIND_LOAD: u8 = 9;
+const KIND_RESPECT_PROTECTION: u8 = 10;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    Spawn {
+        x: usize,
+        y: usize,
+        p_type: ParticleType,
+    },
+    Reseed(u64),
+    Resize {
+        width: usize,
+        height: usize,
+        anchor: Anchor,
+    },
+    Heat {
+        x: usize,
+        y: usize,
+        radius: usize,
+        amount: f32,
+    },
+    Protect {
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        protected: bool,
+    },
+    Gravity {
+        x: f32,
+        y: f32,
+    },
+    EdgeMode(EdgeMode),
+    ScanOrder(ScanOrder),
+    Parallel(bool),
+    /// A `save_state` snapshot taken right after the world was replaced.
+    Load(Vec<u8>),
+    RespectProtection(bool),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub tick: u64,
+    pub edit: Edit,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recording {
+    pub initial_state: Vec<u8>,
+    pub events: Vec<Event>,
+}
+
+pub(crate) struct Replay {
+    events: Vec<Event>,
+    next: usize,
+}
+
+impl Recording {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&(self.initial_state.len() as u32).to_le_bytes());
+        data.extend_from_slice(&self.initial_state);
+        data.extend_from_slice(&(self.events.len() as u32).to_le_bytes());
+
+        for event in &self.events {
+            data.extend_from_slice(&event.tick.to_le_bytes());
+
+            match event.edit {
+                Edit::Spawn { x, y, p_type } => {
+                    data.push(KIND_SPAWN);
+                    data.extend_from_slice(&(x as u32).to_le_bytes());
+                    data.extend_from_slice(&(y as u32).to_le_bytes());
+                    data.push(p_type.id());
+                }
+                Edit::Reseed(seed) => {
+                    data.push(KIND_RESEED);
+                    data.extend_from_slice(&seed.to_le_bytes());
+                }
+                Edit::Resize {
+                    width,
+                    height,
+                    anchor,
+                } => {
+                    data.push(KIND_RESIZE);
+                    data.extend_from_slice(&(width as u32).to_le_bytes());
+                    data.extend_from_slice(&(height as u32).to_le_bytes());
+                    data.push(anchor as u8);
+                }
+                Edit::Heat {
+                    x,
+                    y,
+                    radius,
+                    amount,
+                } => {
+                    data.push(KIND_HEAT);
+                    data.extend_from_slice(&(x as u32).to_le_bytes());
+                    data.extend_from_slice(&(y as u32).to_le_bytes());
+                    data.extend_from_slice(&(radius as u32).to_le_bytes());
+                    data.extend_from_slice(&amount.to_le_bytes());
+                }
+                Edit::Protect {
+                    x,
+                    y,
+                    width,
+                    height,
+                    protected,
+                } => {
+                    data.push(KIND_PROTECT);
+                    data.extend_from_slice(&(x as u32).to_le_bytes());
+                    data.extend_from_slice(&(y as u32).to_le_bytes());
+                    data.extend_from_slice(&(width as u32).to_le_bytes());
+                    data.extend_from_slice(&(height as u32).to_le_bytes());
+                    data.push(protected as u8);
+                }
+                Edit::Gravity { x, y } => {
+                    data.push(KIND_GRAVITY);
+                    data.extend_from_slice(&x.to_le_bytes());
+                    data.extend_from_slice(&y.to_le_bytes());
+                }
+                Edit::EdgeMode(edge_mode) => {
+                    data.push(KIND_EDGE_MODE);
+                    data.push(edge_mode as u8);
+                }
+                Edit::ScanOrder(scan_order) => {
+                    data.push(KIND_SCAN_ORDER);
+                    data.push(scan_order as u8);
+                }
+                Edit::Parallel(parallel) => {
+                    data.push(KIND_PARALLEL);
+                    data.push(parallel as u8);
+                }
+                Edit::Load(ref state) => {
+                    data.push(KIND_LOAD);
+                    data.extend_from_slice(&(state.len() as u32).to_le_bytes());
+                    data.extend_from_slice(state);
+                }
+                Edit::RespectProtection(respect) => {
+                    data.push(KIND_RESPECT_PROTECTION);
+                    data.push(respect as u8);
+                }
+            }
+        }
+
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Recording, StateError> {
+        let mut reader = Reader::new(data);
+
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let state_len = reader.u32()? as usize;
+        let initial_state = reader.bytes(state_len)?.to_vec();
+
+        let count = reader.u32()? as usize;
+        let mut events = Vec::new();
+
+        for _ in 0..count {
+            let tick = reader.u64()?;
+
+            let edit = match reader.u8()? {
+                KIND_SPAWN => {
+                    let x = reader.u32()? as usize;
+                    let y = reader.u32()? as usize;
+                    let t = reader.u8()?;
+                    let p_type = ParticleType::from_u8(t).ok_or(StateError::UnknownParticle(t))?;
+                    Edit::Spawn { x, y, p_type }
+                }
+                KIND_RESEED => Edit::Reseed(reader.u64()?),
+                KIND_RESIZE => {
+                    let width = reader.u32()? as usize;
+                    let height = reader.u32()? as usize;
+                    let anchor = Anchor::from_u8(reader.u8()?).ok_or(StateError::Corrupt)?;
+
+                    if width < 2 || height < 2 {
+                        return Err(StateError::Corrupt);
+                    }
+
+                    Edit::Resize {
+                        width,
+                        height,
+                        anchor,
+                    }
+                }
+                KIND_HEAT => Edit::Heat {
+                    x: reader.u32()? as usize,
+                    y: reader.u32()? as usize,
+                    radius: reader.u32()? as usize,
+                    amount: reader.f32()?,
+                },
+                KIND_PROTECT => Edit::Protect {
+                    x: reader.u32()? as usize,
+                    y: reader.u32()? as usize,
+                    width: reader.u32()? as usize,
+                    height: reader.u32()? as usize,
+                    protected: reader.bool()?,
+                },
+                KIND_GRAVITY => Edit::Gravity {
+                    x: reader.f32()?,
+                    y: reader.f32()?,
+                },
+                KIND_EDGE_MODE => {
+                    Edit::EdgeMode(EdgeMode::from_u8(reader.u8()?).ok_or(StateError::Corrupt)?)
+                }
+                KIND_SCAN_ORDER => {
+                    Edit::ScanOrder(ScanOrder::from_u8(reader.u8()?).ok_or(StateError::Corrupt)?)
+                }
+                KIND_PARALLEL => Edit::Parallel(reader.bool()?),
+                KIND_LOAD => {
+                    let length = reader.u32()? as usize;
+                    Edit::Load(reader.bytes(length)?.to_vec())
+                }
+                KIND_RESPECT_PROTECTION => Edit::RespectProtection(reader.bool()?),
+                _ => return Err(StateError::Corrupt),
+            };
+
+            events.push(Event { tick, edit });
+        }
+
+        reader.finish()?;
+
+        Ok(Recording {
+            initial_state,
+            events,
+        })
+    }
+}
+
+impl Simulation {
+    /// Starts recording edits from the current state, discarding any
+    /// recording in progress.
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Recording {
+            initial_state: self.save_state(),
+            events: Vec::new(),
+        });
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.recording.take()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Restores the recording's snapshot and replays its edits as the world
+    /// is stepped. Edits made while replaying are applied on top.
+    pub fn start_replay(&mut self, recording: Recording) -> Result<(), StateError> {
+        self.load_state(&recording.initial_state)?;
+        self.replay = Some(Replay {
+            events: recording.events,
+            next: 0,
+        });
+        self.advance_replay();
+
+        Ok(())
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    pub fn stop_replay(&mut self) {
+        self.replay = None;
+    }
+
+    pub(crate) fn record(&mut self, edit: Edit) {
+        let tick = self.tick;
+
+        if let Some(recording) = &mut self.recording {
+            recording.events.push(Event { tick, edit });
+        }
+    }
+
+    /// Records the world after it was replaced as a whole on `tick`, by
+    /// loading or importing, as a snapshot to restore on replay. Loading a
+    /// state moves the clock, so the tick is passed in from before the load.
+    pub(crate) fn record_load(&mut self, tick: u64) {
+        if self.recording.is_some() {
+            let edit = Edit::Load(self.save_state());
+            if let Some(recording) = &mut self.recording {
+                recording.events.push(Event { tick, edit });
+            }
+        }
+    }
+
+    /// Applies every replayed edit due at the current tick, ending the replay
+    /// once all have been applied.
+    pub(crate) fn advance_replay(&mut self) {
+        while let Some(replay) = &mut self.replay {
+            let event = match replay.events.get(replay.next) {
+                Some(event) if event.tick <= self.tick => event.clone(),
+                Some(_) => return,
+                None => {
+                    self.replay = None;
+                    return;
+                }
+            };
+
+            replay.next += 1;
+
+            match event.edit {
+                // written as recorded, whatever the spawn mode and protection
+                Edit::Spawn { x, y, p_type } => {
+                    if x < self.width && y < self.height {
+                        let index = self.get_index(x, y);
+                        self.edit_cell(index, p_type);
+                    }
+                }
+                Edit::Reseed(seed) => self.reseed(seed),
+                Edit::Resize {
+                    width,
+                    height,
+                    anchor,
+                } => self.resize(width, height, anchor),
+                Edit::Heat {
+                    x,
+                    y,
+                    radius,
+                    amount,
+                } => self.paint_heat(x, y, radius, amount),
+                Edit::Protect {
+                    x,
+                    y,
+                    width,
+                    height,
+                    protected,
+                } => self.protect_rect(x, y, width, height, protected),
+                Edit::Gravity { x, y } => self.set_gravity(x, y),
+                Edit::EdgeMode(edge_mode) => self.set_edge_mode(edge_mode),
+                Edit::ScanOrder(scan_order) => self.set_scan_order(scan_order),
+                Edit::Parallel(parallel) => self.set_parallel(parallel),
+                Edit::Load(state) => {
+                    // only a damaged recording holds a snapshot that fails
+                    if self.load_state(&state).is_err() {
+                        self.replay = None;
+                    }
+                }
+                Edit::RespectProtection(respect) => self.set_reactions_respect_protection(respect),
+            }
+        }
+    }
+}
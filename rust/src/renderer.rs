@@ -1,3 +1,6 @@
+use crate::material::MATERIALS;
+use crate::particle::Particle;
+use crate::particle::ParticleType;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{
@@ -10,6 +13,7 @@ pub struct ProgramInfo {
     pub a_texture_coordinate: i32,
     pub u_sampler: Option<WebGlUniformLocation>,
     pub u_time: Option<WebGlUniformLocation>,
+    #[allow(dead_code)]
     pub program: Option<WebGlProgram>,
 }
 
@@ -24,6 +28,8 @@ pub struct Renderer {
     pub context: Option<WebGlRenderingContext>,
     pub program_info: Option<ProgramInfo>,
     pub buffers: Option<Buffers>,
+    /// Texture upload buffer, two bytes per cell.
+    pub pixels: Vec<u8>,
 }
 
 impl Renderer {
@@ -48,7 +54,17 @@ impl Renderer {
         Ok(())
     }
 
-    pub fn render(&self, framebuffer: &[u8], width: u32, height: u32, time: f32) {
+    /// Packs the parts of each particle the shader needs into a luminance and
+    /// alpha texel: the color seed as luminance and the material id as alpha.
+    fn project(&mut self, particles: &[Particle]) {
+        self.pixels.clear();
+        self.pixels
+            .extend(particles.iter().flat_map(|p| [p.seed, p.p_type.id()]));
+    }
+
+    pub fn render(&mut self, particles: &[Particle], width: u32, height: u32, time: f32) {
+        self.project(particles);
+
         let context = self.context.as_ref().unwrap();
         let buffers = self.buffers.as_ref().unwrap();
         let program_info = self.program_info.as_ref().unwrap();
@@ -64,7 +80,7 @@ impl Renderer {
         let u_time = program_info.u_time.as_ref();
 
         // vertex buffer
-        context.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&vertex_buffer));
+        context.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(vertex_buffer));
         context.vertex_attrib_pointer_with_i32(
             a_vertex_position as u32,
             2,
@@ -79,13 +95,13 @@ impl Renderer {
         // indices
         context.bind_buffer(
             WebGlRenderingContext::ELEMENT_ARRAY_BUFFER,
-            Some(&index_buffer),
+            Some(index_buffer),
         );
 
         // texture coordinates
         context.bind_buffer(
             WebGlRenderingContext::ARRAY_BUFFER,
-            Some(&texture_coordinate_buffer),
+            Some(texture_coordinate_buffer),
         );
 
         context.vertex_attrib_pointer_with_i32(
@@ -100,20 +116,20 @@ impl Renderer {
         context.enable_vertex_attrib_array(a_texture_coordinate as u32);
 
         // texture
-        context.bind_texture(WebGlRenderingContext::TEXTURE_2D, Some(&texture));
+        context.bind_texture(WebGlRenderingContext::TEXTURE_2D, Some(texture));
         context.active_texture(WebGlRenderingContext::TEXTURE0);
 
         let _result = context
             .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
                 WebGlRenderingContext::TEXTURE_2D,
                 0,
-                WebGlRenderingContext::ALPHA as i32,
+                WebGlRenderingContext::LUMINANCE_ALPHA as i32,
                 width as i32,
                 height as i32,
                 0,
-                WebGlRenderingContext::ALPHA,
+                WebGlRenderingContext::LUMINANCE_ALPHA,
                 WebGlRenderingContext::UNSIGNED_BYTE,
-                Some(framebuffer),
+                Some(&self.pixels),
             );
 
         // uniforms
@@ -133,7 +149,7 @@ impl Renderer {
         let indices_len = 6;
         context.draw_elements_with_i32(
             WebGlRenderingContext::TRIANGLES,
-            indices_len as i32,
+            indices_len,
             WebGlRenderingContext::UNSIGNED_BYTE,
             0,
         );
@@ -220,6 +236,9 @@ impl Renderer {
 
         context.bind_texture(WebGlRenderingContext::TEXTURE_2D, Some(&texture));
 
+        // rows of two-byte texels are not padded to four bytes
+        context.pixel_storei(WebGlRenderingContext::UNPACK_ALIGNMENT, 1);
+
         let default_texture: [u8; 3] = [0, 0, 0];
 
         let _result = context
@@ -266,14 +285,14 @@ impl Renderer {
             texture: Some(texture),
         };
 
-        return Ok(buffers);
+        Ok(buffers)
     }
 
     fn create_program(&self) -> Result<ProgramInfo, String> {
         let context = self.context.as_ref().unwrap();
 
         let vertex_shader = self.compile_shader(
-            &context,
+            context,
             WebGlRenderingContext::VERTEX_SHADER,
             r#"
             attribute vec2 aVertexPosition;
@@ -290,63 +309,42 @@ impl Renderer {
         )?;
 
         let fragment_shader = self.compile_shader(
-            &context,
+            context,
             WebGlRenderingContext::FRAGMENT_SHADER,
-            r#"
+            &format!(
+                r#"
             precision mediump float;
 
             varying highp vec2 vTextureCoord;
             uniform sampler2D uSampler;
             uniform float uTime;
 
-            float random(in vec2 v) {
-                return fract(sin(dot(v.xy,
-                    vec2(419.5548,66.784)))
-                    * 4611.778291);
-            }
-
-            void main() {
+            void main() {{
                 vec4 color = texture2D(uSampler, vTextureCoord);
 
-                float r = random(fract(vTextureCoord));
-
-                if (color.a == (6.0 / 255.0)) {
-                    // oil
-                    color = vec4(55.0/255.0, 58.0/255.0, 54.0/255.0, 1.0);
-                } else if (color.a == (5.0 / 255.0)) {
-                    // fire
-                    color = vec4(170.0/255.0, 16.0/255.0, 0.0/255.0, 1.0);
-                } else if (color.a == (4.0 / 255.0)) {
-                    // plant
-                    color = vec4(50.0/255.0, 205.0/255.0, 50.0/255.0, 1.0);
-                } else if (color.a == (3.0 / 255.0)) {
-                    // water
-                    color = vec4(128.0/255.0, 197.0/255.0, 222.0/255.0, 1.0);
-                } else if (color.a == (2.0 / 255.0)) {
-                    // sand
-                    color = vec4(194.0/255.0, 178.0/255.0, 128.0/255.0, 1.0);
-                } else if (color.a == (1.0 / 255.0)) {
-                    // wall
-                    color = vec4(220.0/255.0, 220.0/255.0, 220.0/255.0, 1.0);
-                } else if (color.a == 0.0) {
+                float r = color.r;
+
+                {palette}if (color.a == 0.0) {{
                     // empty
                     color = vec4(0.0, 0.0, 0.0, 1.0);
                     gl_FragColor = color;
                     return;
-                } else {
+                }} else {{
                     color = vec4(1.0, 0.0, 0.0, 1.0);
-                }
+                }}
 
 
                 float f = smoothstep(0.4, 0.5, r);
                 color = vec4(mix(color.xyz, color.xyz*1.05, f), 1.0);
 
                 gl_FragColor = color;
-            }
+            }}
         "#,
+                palette = material_palette()
+            ),
         )?;
 
-        let program = self.link_program(&context, &vertex_shader, &fragment_shader)?;
+        let program = self.link_program(context, &vertex_shader, &fragment_shader)?;
 
         context.use_program(Some(&program));
 
@@ -357,13 +355,13 @@ impl Renderer {
 
         let program_info = ProgramInfo {
             program: Some(program),
-            a_vertex_position: a_vertex_position,
-            a_texture_coordinate: a_texture_coordinate,
-            u_sampler: u_sampler,
-            u_time: u_time,
+            a_vertex_position,
+            a_texture_coordinate,
+            u_sampler,
+            u_time,
         };
 
-        return Ok(program_info);
+        Ok(program_info)
     }
 
     fn compile_shader(
@@ -418,3 +416,26 @@ impl Renderer {
         }
     }
 }
+
+/// The shader branches coloring every non-empty material, built from the
+/// registry so new materials need no shader changes.
+fn material_palette() -> String {
+    MATERIALS
+        .iter()
+        .filter(|m| m.id != ParticleType::Empty)
+        .map(|m| {
+            let [r, g, b] = m.color;
+            format!(
+                "if (color.a == ({}.0 / 255.0)) {{\n\
+                 // {}\n\
+                 color = vec4({}.0/255.0, {}.0/255.0, {}.0/255.0, 1.0);\n\
+                 }} else ",
+                m.id.id(),
+                m.name.to_lowercase(),
+                r,
+                g,
+                b
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,120 @@
+//! Resizing a running world while keeping its contents.
+
+use crate::chunks::Chunks;
+use crate::edge::EdgeMode;
+use crate::heat::AMBIENT_TEMPERATURE;
+use crate::particle::color_seed;
+use crate::particle::Particle;
+use crate::particle::ParticleType;
+use crate::recording::Edit;
+use crate::simulation::is_border;
+use crate::simulation::Simulation;
+use crate::stats::MaterialCounts;
+
+/// The part of the world that stays in place when it is resized.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft = 0,
+    Center = 1,
+    /// Centered horizontally, with the floor kept at the bottom.
+    Bottom = 2,
+}
+
+impl Anchor {
+    pub fn from_u8(value: u8) -> Option<Anchor> {
+        match value {
+            0 => Some(Anchor::TopLeft),
+            1 => Some(Anchor::Center),
+            2 => Some(Anchor::Bottom),
+            _ => None,
+        }
+    }
+
+    /// How far old cells move along one axis.
+    fn offset(self, old: usize, new: usize, vertical: bool) -> isize {
+        let difference = new as isize - old as isize;
+
+        match self {
+            Anchor::TopLeft => 0,
+            Anchor::Bottom if vertical => difference,
+            Anchor::Center | Anchor::Bottom => difference / 2,
+        }
+    }
+}
+
+impl Simulation {
+    /// Resizes the world, keeping the contents at `anchor` in place. Cells
+    /// that no longer fit are cropped and new cells are empty. In wall mode
+    /// the old wall border is removed and a new one is painted around the new
+    /// edges. Protection moves with the contents.
+    /// Sizes below 2x2 are ignored.
+    pub fn resize(&mut self, width: usize, height: usize, anchor: Anchor) {
+        if width < 2 || height < 2 {
+            return;
+        }
+
+        self.record(Edit::Resize {
+            width,
+            height,
+            anchor,
+        });
+
+        let walls = self.edge_mode == EdgeMode::Wall;
+        let dx = anchor.offset(self.width, width, false);
+        let dy = anchor.offset(self.height, height, true);
+
+        let mut particles = vec![Particle::new(ParticleType::Empty, 0); width * height];
+        let mut clocks = vec![self.clock; width * height];
+        let mut temperatures = vec![AMBIENT_TEMPERATURE; width * height];
+        let mut protected = vec![false; width * height];
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (nx, ny) = (x as isize + dx, y as isize + dy);
+                if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+                    continue;
+                }
+
+                let old_index = self.get_index(x, y);
+                let new_index = ny as usize * width + nx as usize;
+
+                if walls && is_border(x, y, self.width, self.height) {
+                    if self.particles[old_index].p_type == ParticleType::Wall {
+                        continue;
+                    }
+                } else {
+                    protected[new_index] = self.protected[old_index];
+                }
+
+                let particle = self.particles[old_index];
+
+                particles[new_index] = particle;
+                clocks[new_index] = self.clocks[old_index];
+                temperatures[new_index] = self.temperatures[old_index];
+            }
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                if walls && is_border(x, y, width, height) {
+                    let index = y * width + x;
+                    particles[index] =
+                        Particle::new(ParticleType::Wall, color_seed(index, self.tick));
+                    protected[index] = true;
+                }
+            }
+        }
+
+        self.particles = particles;
+        self.clocks = clocks;
+        self.temperatures = temperatures;
+        self.protected = protected;
+        self.width = width;
+        self.height = height;
+        self.history.clear();
+        self.counts = MaterialCounts::new(&self.particles);
+        self.chunks = Chunks::new(width, height);
+        self.chunks.set_wrap(self.edge_mode == EdgeMode::Wrap);
+    }
+}
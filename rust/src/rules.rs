@@ -0,0 +1,392 @@
+//! The per-particle update rules.
+//!
+//! Rules see the grid through `Cells`, a raw view that lets chunks far enough
+//! apart be stepped from different threads at once.
+
+use rand::Rng;
+
+use crate::chunks::Chunks;
+use crate::edge::EdgeMode;
+use crate::material::Movement;
+use crate::material::Reaction;
+use crate::motion::Frame;
+use crate::particle::color_seed;
+use crate::particle::Direction;
+use crate::particle::Particle;
+use crate::particle::ParticleType;
+use crate::stats::MaterialCounts;
+
+#[derive(Clone, Copy)]
+pub(crate) struct Cells {
+    particles: *mut Particle,
+    clocks: *mut u8,
+    temperatures: *mut f32,
+    len: usize,
+}
+
+// Safety: see `Cells::new`.
+unsafe impl Send for Cells {}
+unsafe impl Sync for Cells {}
+
+impl Cells {
+    /// # Safety
+    ///
+    /// While any copy of the view is in use the grid must not be accessed in
+    /// any other way, and copies used from different threads at the same time
+    /// must only touch disjoint cells.
+    pub(crate) unsafe fn new(
+        particles: &mut [Particle],
+        clocks: &mut [u8],
+        temperatures: &mut [f32],
+    ) -> Cells {
+        assert_eq!(particles.len(), clocks.len());
+        assert_eq!(particles.len(), temperatures.len());
+
+        Cells {
+            particles: particles.as_mut_ptr(),
+            clocks: clocks.as_mut_ptr(),
+            temperatures: temperatures.as_mut_ptr(),
+            len: particles.len(),
+        }
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Particle {
+        assert!(index < self.len);
+        unsafe { *self.particles.add(index) }
+    }
+
+    pub(crate) fn set(&self, index: usize, particle: Particle) {
+        assert!(index < self.len);
+        unsafe { *self.particles.add(index) = particle }
+    }
+
+    pub(crate) fn clock(&self, index: usize) -> u8 {
+        assert!(index < self.len);
+        unsafe { *self.clocks.add(index) }
+    }
+
+    pub(crate) fn set_clock(&self, index: usize, clock: u8) {
+        assert!(index < self.len);
+        unsafe { *self.clocks.add(index) = clock }
+    }
+
+    /// Exchanges the temperatures of two cells, so heat travels with the
+    /// particles that hold it.
+    pub(crate) fn swap_temperatures(&self, a: usize, b: usize) {
+        assert!(a < self.len && b < self.len);
+        unsafe { std::ptr::swap(self.temperatures.add(a), self.temperatures.add(b)) }
+    }
+}
+
+pub(crate) struct Rules<'a> {
+    pub(crate) cells: Cells,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) edge_mode: EdgeMode,
+    pub(crate) clock: u8,
+    /// Acceleration along x and y in velocity units.
+    pub(crate) gravity: [i32; 2],
+    /// Neighbor directions relative to gravity, or `None` without gravity.
+    pub(crate) frame: Option<Frame>,
+    pub(crate) rng: &'a mut rand_pcg::Lcg64Xsh32,
+    pub(crate) chunks: &'a Chunks,
+    /// Cells reactions must leave alone, if they respect protection.
+    pub(crate) protected: Option<&'a [bool]>,
+    pub(crate) counts: &'a MaterialCounts,
+}
+
+impl Rules<'_> {
+    pub(crate) fn update(&mut self, x: usize, y: usize) {
+        let index = self.get_index(x, y);
+
+        let mut particle = self.cells.get(index);
+
+        if self.cells.clock(index).wrapping_sub(self.clock) == 1 {
+            return;
+        }
+
+        if particle.p_type == ParticleType::Empty {
+            return;
+        }
+
+        let material = particle.p_type.material();
+
+        particle.age = particle.age.saturating_add(1);
+        self.cells.set(index, particle);
+
+        if material.lifetime > 0 {
+            let lifetime = material.lifetime - particle.seed % (material.lifetime / 2 + 1);
+            if particle.age >= lifetime {
+                self.set_particle(index, ParticleType::Empty);
+                return;
+            }
+
+            // keep aging until it expires
+            self.chunks.keep_awake(x, y);
+        }
+
+        match material.reaction {
+            Reaction::Grow { feeds_on, chance } => self.grow(x, y, feeds_on, chance),
+            Reaction::None => (),
+        };
+
+        if self.cells.get(index) != particle {
+            return;
+        }
+
+        match material.movement {
+            Movement::Powder | Movement::Liquid if self.fall(x, y) => (),
+            Movement::Powder => self.update_powder(x, y),
+            Movement::Liquid => self.update_liquid(x, y, 1),
+            Movement::Gas => self.update_liquid(x, y, -1),
+            Movement::Static => (),
+        };
+    }
+
+    pub(crate) fn get_index(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+
+    fn is_protected(&self, index: usize) -> bool {
+        self.protected.is_some_and(|protected| protected[index])
+    }
+
+    /// Creates a new particle during a step.
+    fn set_particle(&mut self, index: usize, p_type: ParticleType) {
+        let seed = color_seed(index, self.clock as u64);
+        self.put_particle(index, Particle::new(p_type, seed));
+    }
+
+    /// Changes a cell during a step, marking it as updated this tick and
+    /// keeping the chunks around it awake.
+    fn put_particle(&mut self, index: usize, particle: Particle) {
+        self.counts
+            .replace(self.cells.get(index).p_type, particle.p_type);
+        self.cells.set(index, particle);
+        self.cells.set_clock(index, self.clock.wrapping_add(1));
+        self.chunks
+            .keep_awake(index % self.width, index / self.width);
+    }
+
+    /// Index of the cell `dx`, `dy` away, or `None` if it lies beyond the edge
+    /// of the world.
+    pub(crate) fn neighbor(&self, x: usize, y: usize, dx: isize, dy: isize) -> Option<usize> {
+        let nx = x as isize + dx;
+        let ny = y as isize + dy;
+        let (width, height) = (self.width as isize, self.height as isize);
+
+        if self.edge_mode == EdgeMode::Wrap {
+            return Some(self.get_index(
+                nx.rem_euclid(width) as usize,
+                ny.rem_euclid(height) as usize,
+            ));
+        }
+
+        if nx < 0 || ny < 0 || nx >= width || ny >= height {
+            return None;
+        }
+
+        Some(self.get_index(nx as usize, ny as usize))
+    }
+
+    /// The particle in a neighboring cell. Beyond the edge this is a wall, or
+    /// empty space if particles fall into the void.
+    fn particle_at(&self, index: Option<usize>) -> Particle {
+        match index {
+            Some(index) => self.cells.get(index),
+            None if self.edge_mode == EdgeMode::Void => Particle::new(ParticleType::Empty, 0),
+            None => Particle::new(ParticleType::Wall, 0),
+        }
+    }
+
+    /// Swaps a particle with a neighbor, or deletes it if it moves off the
+    /// edge of the world.
+    pub(crate) fn move_particle(&mut self, index_current: usize, index_new: Option<usize>) {
+        match index_new {
+            Some(index_new) => {
+                let current = self.cells.get(index_current);
+                let new = self.cells.get(index_new);
+
+                self.put_particle(index_current, new);
+                self.put_particle(index_new, current);
+                self.cells.swap_temperatures(index_current, index_new);
+            }
+            None => self.set_particle(index_current, ParticleType::Empty),
+        }
+    }
+
+    /// Whether the particle at `index_current` may move into a neighboring
+    /// cell, with `fall` being 1 for a move along gravity, 0 across it and -1
+    /// against it. Any particle moves into empty space, and swaps with a fluid
+    /// that is lighter than it when sinking or moving sideways, or heavier
+    /// than it when rising.
+    pub(crate) fn can_displace(
+        &self,
+        index_current: usize,
+        index: Option<usize>,
+        fall: isize,
+    ) -> bool {
+        let mover = self.cells.get(index_current).p_type.material();
+        let target = self.particle_at(index).p_type;
+
+        if target == ParticleType::Empty {
+            return true;
+        }
+
+        let target = target.material();
+
+        match target.movement {
+            Movement::Liquid | Movement::Gas if fall < 0 => target.density > mover.density,
+            Movement::Liquid | Movement::Gas => target.density < mover.density,
+            _ => false,
+        }
+    }
+
+    fn neighbors(&self, x: usize, y: usize) -> [Option<usize>; 8] {
+        [
+            self.neighbor(x, y, 0, 1),
+            self.neighbor(x, y, -1, 1),
+            self.neighbor(x, y, 1, 1),
+            self.neighbor(x, y, -1, 0),
+            self.neighbor(x, y, 1, 0),
+            self.neighbor(x, y, 0, -1),
+            self.neighbor(x, y, -1, -1),
+            self.neighbor(x, y, 1, -1),
+        ]
+    }
+
+    fn grow(&mut self, x: usize, y: usize, feeds_on: ParticleType, chance: u8) {
+        let p_type = self.cells.get(self.get_index(x, y)).p_type;
+
+        for index in self.neighbors(x, y).iter().flatten() {
+            if self.cells.get(*index).p_type == feeds_on && !self.is_protected(*index) {
+                let r = self.rng.gen_range(0, 100);
+                if r < chance {
+                    self.set_particle(*index, p_type);
+                } else {
+                    // keep growing into this neighbor on a later tick
+                    self.chunks.keep_awake(x, y);
+                }
+            }
+        }
+    }
+
+    /// Moves a liquid along `fall`, which is 1 for liquids falling with
+    /// gravity and -1 for gases rising against it. Directions are relative to
+    /// gravity, so "down" is whichever way it points.
+    fn update_liquid(&mut self, x: usize, y: usize, fall: isize) {
+        let frame = match self.frame {
+            Some(frame) if fall < 0 => frame.flipped(),
+            Some(frame) => frame,
+            None => return,
+        };
+
+        let index_current = self.get_index(x, y);
+        let index_down = self.neighbor(x, y, frame.down.0, frame.down.1);
+        let index_down_left = self.neighbor(x, y, frame.down_left.0, frame.down_left.1);
+        let index_down_right = self.neighbor(x, y, frame.down_right.0, frame.down_right.1);
+        let index_left = self.neighbor(x, y, frame.left.0, frame.left.1);
+        let index_right = self.neighbor(x, y, frame.right.0, frame.right.1);
+
+        let r = self.rng.gen_range(0, 2);
+
+        // Sinking diagonally into a lighter fluid comes before spreading, so
+        // the fluid underneath gets pushed up rather than stranded.
+        let sinks_into = |index: Option<usize>| {
+            self.particle_at(index).p_type != ParticleType::Empty
+                && self.can_displace(index_current, index, fall)
+        };
+        let sink_left = sinks_into(index_down_left);
+        let sink_right = sinks_into(index_down_right);
+
+        let direction = match (
+            self.can_displace(index_current, index_down_left, fall),
+            self.can_displace(index_current, index_down, fall),
+            self.can_displace(index_current, index_down_right, fall),
+            self.can_displace(index_current, index_left, 0),
+            self.can_displace(index_current, index_right, 0),
+        ) {
+            (_, true, _, _, _) => Direction::Down,
+            _ if sink_left && sink_right => {
+                if r == 0 {
+                    Direction::DownLeft
+                } else {
+                    Direction::DownRight
+                }
+            }
+            _ if sink_left => Direction::DownLeft,
+            _ if sink_right => Direction::DownRight,
+            (_, _, _, true, true) => {
+                if r == 0 {
+                    Direction::Left
+                } else {
+                    Direction::Right
+                }
+            }
+            (_, _, _, true, _) => Direction::Left,
+            (_, _, _, _, true) => Direction::Right,
+            (true, _, true, _, _) => {
+                if r == 0 {
+                    Direction::DownLeft
+                } else {
+                    Direction::DownRight
+                }
+            }
+            (true, _, _, _, _) => Direction::DownLeft,
+            (_, _, true, _, _) => Direction::DownRight,
+            _ => Direction::None,
+        };
+
+        let index_new = match direction {
+            Direction::Down => index_down,
+            Direction::DownLeft => index_down_left,
+            Direction::DownRight => index_down_right,
+            Direction::Left => index_left,
+            Direction::Right => index_right,
+            _ => return,
+        };
+
+        self.move_particle(index_current, index_new);
+    }
+
+    fn update_powder(&mut self, x: usize, y: usize) {
+        let frame = match self.frame {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        let index_current = self.get_index(x, y);
+        let index_down = self.neighbor(x, y, frame.down.0, frame.down.1);
+        let index_down_left = self.neighbor(x, y, frame.down_left.0, frame.down_left.1);
+        let index_down_right = self.neighbor(x, y, frame.down_right.0, frame.down_right.1);
+
+        let r = self.rng.gen_range(0, 2);
+
+        let direction = match (
+            self.can_displace(index_current, index_down_left, 1),
+            self.can_displace(index_current, index_down, 1),
+            self.can_displace(index_current, index_down_right, 1),
+        ) {
+            (_, true, _) => Direction::Down,
+            (true, _, true) => {
+                if r == 0 {
+                    Direction::DownLeft
+                } else {
+                    Direction::DownRight
+                }
+            }
+            (true, _, _) => Direction::DownLeft,
+            (_, _, true) => Direction::DownRight,
+            _ => Direction::None,
+        };
+
+        let index_new = match direction {
+            Direction::Down => index_down,
+            Direction::DownLeft => index_down_left,
+            Direction::DownRight => index_down_right,
+            _ => return,
+        };
+
+        self.move_particle(index_current, index_new);
+    }
+}
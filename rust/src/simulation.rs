@@ -0,0 +1,380 @@
+use rand::{Rng, SeedableRng};
+
+use crate::brush::SpawnMode;
+use crate::brush::Stroke;
+use crate::chunks::Chunks;
+use crate::chunks::CHUNK_SIZE;
+use crate::edge::EdgeMode;
+use crate::heat::AMBIENT_TEMPERATURE;
+use crate::history::History;
+use crate::motion::Frame;
+use crate::motion::DEFAULT_GRAVITY;
+use crate::motion::REACH;
+use crate::particle::color_seed;
+use crate::particle::Particle;
+use crate::particle::ParticleType;
+use crate::protection::border_mask;
+use crate::recording::Edit;
+use crate::recording::Recording;
+use crate::recording::Replay;
+use crate::rules::Cells;
+use crate::rules::Rules;
+use crate::scan::ScanOrder;
+use crate::stats::MaterialCounts;
+
+/// Seed used when no explicit seed is given.
+pub const DEFAULT_SEED: u64 = 419;
+/// Random stream of the brush rng, apart from the default one of `rng`.
+const BRUSH_STREAM: u64 = 0xb5;
+
+/// The particle grid and the rules that move it, free of any browser APIs so it
+/// can be stepped and tested natively.
+pub struct Simulation {
+    pub(crate) particles: Vec<Particle>,
+    pub(crate) clocks: Vec<u8>,
+    pub(crate) temperatures: Vec<f32>,
+    /// Scratch space for the heat flowing into each cell during a step, kept
+    /// at zero between steps.
+    pub(crate) heat_flow: Vec<f32>,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) clock: u8,
+    pub(crate) tick: u64,
+    pub(crate) rng: rand_pcg::Lcg64Xsh32,
+    pub(crate) time: f32,
+    pub(crate) recording: Option<Recording>,
+    pub(crate) replay: Option<Replay>,
+    pub(crate) history: History,
+    pub(crate) chunks: Chunks,
+    pub(crate) parallel: bool,
+    pub(crate) scan_order: ScanOrder,
+    pub(crate) edge_mode: EdgeMode,
+    pub(crate) gravity: [f32; 2],
+    pub(crate) stroke: Option<Stroke>,
+    pub(crate) spawn_mode: SpawnMode,
+    pub(crate) spray_density: u8,
+    /// Randomness for brushes, kept apart from `rng` so spraying does not
+    /// change how the world evolves.
+    pub(crate) brush_rng: rand_pcg::Lcg64Xsh32,
+    pub(crate) protected: Vec<bool>,
+    pub(crate) reactions_respect_protection: bool,
+    pub(crate) counts: MaterialCounts,
+}
+
+impl Simulation {
+    pub fn new(width: usize, height: usize) -> Simulation {
+        Simulation::with_seed(width, height, DEFAULT_SEED)
+    }
+
+    /// Creates a world whose randomness is fully determined by `seed`.
+    pub fn with_seed(width: usize, height: usize, seed: u64) -> Simulation {
+        let mut particles = vec![Particle::new(ParticleType::Empty, 0); width * height];
+        let clocks: Vec<u8> = vec![0; width * height];
+        let temperatures = vec![AMBIENT_TEMPERATURE; width * height];
+
+        let rng = rand_pcg::Pcg32::seed_from_u64(seed);
+
+        for y in 0..height {
+            for x in 0..width {
+                if is_border(x, y, width, height) {
+                    let index = y * width + x;
+                    particles[index] = Particle::new(ParticleType::Wall, color_seed(index, 0));
+                }
+            }
+        }
+
+        let counts = MaterialCounts::new(&particles);
+
+        Simulation {
+            particles,
+            clocks,
+            temperatures,
+            heat_flow: vec![0.0; width * height],
+            width,
+            height,
+            clock: 0,
+            tick: 0,
+            rng,
+            time: 0.0,
+            recording: None,
+            replay: None,
+            history: History::new(),
+            chunks: Chunks::new(width, height),
+            parallel: false,
+            scan_order: ScanOrder::ColumnMajor,
+            edge_mode: EdgeMode::Wall,
+            gravity: [0.0, DEFAULT_GRAVITY],
+            stroke: None,
+            spawn_mode: SpawnMode::Overwrite,
+            spray_density: 100,
+            brush_rng: rand_pcg::Pcg32::new(seed, BRUSH_STREAM),
+            protected: border_mask(width, height),
+            reactions_respect_protection: false,
+            counts,
+        }
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.record(Edit::Reseed(seed));
+        self.rng = rand_pcg::Pcg32::seed_from_u64(seed);
+        self.brush_rng = rand_pcg::Pcg32::new(seed, BRUSH_STREAM);
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Number of chunks that will be simulated on the next step.
+    pub fn awake_chunks(&self) -> usize {
+        self.chunks.awake_count()
+    }
+
+    /// Number of steps taken since the world was created.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> ParticleType {
+        self.particles[self.get_index(x, y)].p_type
+    }
+
+    /// The particle at `x`, `y`, with its age, color seed, flags and velocity.
+    pub fn particle(&self, x: usize, y: usize) -> Particle {
+        self.particles[self.get_index(x, y)]
+    }
+
+    /// Places a particle, ignoring coordinates outside the world, protected
+    /// cells and cells the spawn mode and spray density do not admit.
+    pub fn spawn(&mut self, x: usize, y: usize, p_type: ParticleType) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        let index = self.get_index(x, y);
+        if !self.protected[index] && self.admits(index) {
+            self.edit_cell(index, p_type);
+        }
+    }
+
+    /// Steps awake chunks in four passes of non-adjacent chunks, running each
+    /// pass on rayon's thread pool. Every chunk draws from its own random
+    /// stream, so the result for a given seed does not depend on the number
+    /// of threads, but differs from the serial step.
+    ///
+    /// Wasm builds do not use threads, even in browsers that support them:
+    /// there the chunks of each pass are stepped one after another, with the
+    /// same result but no speedup.
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.record(Edit::Parallel(parallel));
+        self.parallel = parallel;
+    }
+
+    pub fn is_parallel(&self) -> bool {
+        self.parallel
+    }
+
+    pub fn set_scan_order(&mut self, scan_order: ScanOrder) {
+        self.record(Edit::ScanOrder(scan_order));
+        self.scan_order = scan_order;
+    }
+
+    pub fn scan_order(&self) -> ScanOrder {
+        self.scan_order
+    }
+
+    /// Sets how the edges of the world behave, removing the wall border when
+    /// leaving wall mode and painting it again when returning to it.
+    pub fn set_edge_mode(&mut self, edge_mode: EdgeMode) {
+        self.record(Edit::EdgeMode(edge_mode));
+        if (self.edge_mode == EdgeMode::Wall) != (edge_mode == EdgeMode::Wall) {
+            self.set_border(edge_mode == EdgeMode::Wall);
+        }
+        self.edge_mode = edge_mode;
+        self.chunks.set_wrap(edge_mode == EdgeMode::Wrap);
+        self.chunks.wake_all();
+    }
+
+    pub fn edge_mode(&self) -> EdgeMode {
+        self.edge_mode
+    }
+
+    pub fn step(&mut self) {
+        if self.parallel {
+            self.step_checkerboard();
+        } else {
+            self.step_serial();
+        }
+
+        self.update_heat();
+        self.chunks.swap();
+        self.clock = self.clock.wrapping_add(1);
+        self.tick += 1;
+        self.time += 0.16;
+
+        self.advance_replay();
+    }
+}
+
+impl Simulation {
+    fn step_serial(&mut self) {
+        // The grid is only reached through `rules` until the pass is over.
+        let cells = unsafe {
+            Cells::new(
+                &mut self.particles,
+                &mut self.clocks,
+                &mut self.temperatures,
+            )
+        };
+        let protected = Some(&self.protected[..]).filter(|_| self.reactions_respect_protection);
+        let mut rules = Rules {
+            cells,
+            width: self.width,
+            height: self.height,
+            edge_mode: self.edge_mode,
+            clock: self.clock,
+            gravity: self.gravity_units(),
+            frame: Frame::new(self.gravity),
+            rng: &mut self.rng,
+            chunks: &self.chunks,
+            protected,
+            counts: &self.counts,
+        };
+
+        let chunks = &self.chunks;
+        rules.scan(self.scan_order, 0..self.width, 0..self.height, |x, y| {
+            chunks.is_awake(x / CHUNK_SIZE, y / CHUNK_SIZE)
+        });
+    }
+
+    fn step_checkerboard(&mut self) {
+        let tick_seed: u64 = self.rng.gen();
+        let cells = unsafe {
+            Cells::new(
+                &mut self.particles,
+                &mut self.clocks,
+                &mut self.temperatures,
+            )
+        };
+        let (width, height, clock) = (self.width, self.height, self.clock);
+        let gravity = self.gravity_units();
+        let frame = Frame::new(self.gravity);
+        let scan_order = self.scan_order;
+        let edge_mode = self.edge_mode;
+        let protected = Some(&self.protected[..]).filter(|_| self.reactions_respect_protection);
+
+        // Wrapping makes the first and last chunks of a row or column
+        // neighbors, which keeps the passes apart only for an even number of
+        // chunks with the last one wide enough for a cell update to reach
+        // into it from both sides.
+        let wraps_apart = |size: usize, count: usize| {
+            count.is_multiple_of(2)
+                && (size.is_multiple_of(CHUNK_SIZE) || size % CHUNK_SIZE >= 2 * REACH)
+        };
+        let concurrent = edge_mode != EdgeMode::Wrap
+            || (wraps_apart(width, self.chunks.columns())
+                && wraps_apart(height, self.chunks.rows()));
+        let chunks = &self.chunks;
+        let counts = &self.counts;
+
+        // Chunks in the same pass are a whole chunk apart, more than twice
+        // `REACH`, so the cells they read and write never overlap.
+        for pass in 0..4 {
+            let batch: Vec<(usize, usize)> = (0..chunks.rows())
+                .filter(|row| row % 2 == pass / 2)
+                .flat_map(|row| {
+                    (0..chunks.columns())
+                        .filter(move |column| column % 2 == pass % 2)
+                        .map(move |column| (column, row))
+                })
+                .filter(|&(column, row)| chunks.is_awake(column, row))
+                .collect();
+
+            for_each_chunk(&batch, concurrent, |&(column, row)| {
+                let stream = (row * chunks.columns() + column) as u64;
+                let mut rng = rand_pcg::Pcg32::new(tick_seed, stream);
+                let mut rules = Rules {
+                    cells,
+                    width,
+                    height,
+                    edge_mode,
+                    clock,
+                    gravity,
+                    frame,
+                    rng: &mut rng,
+                    chunks,
+                    protected,
+                    counts,
+                };
+
+                rules.scan(
+                    scan_order,
+                    column * CHUNK_SIZE..((column + 1) * CHUNK_SIZE).min(width),
+                    row * CHUNK_SIZE..((row + 1) * CHUNK_SIZE).min(height),
+                    |_, _| true,
+                );
+            });
+        }
+    }
+
+    pub(crate) fn get_index(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+
+    /// Applies a user edit to a single cell, tracking it for undo.
+    pub(crate) fn edit_cell(&mut self, index: usize, p_type: ParticleType) {
+        let before = self.particles[index].p_type;
+        self.history.track(index, before, p_type);
+        self.write_cell(index, p_type);
+    }
+
+    /// Writes a cell on behalf of the user, recording it for replay.
+    pub(crate) fn write_cell(&mut self, index: usize, p_type: ParticleType) {
+        let (x, y) = (index % self.width, index / self.width);
+        self.record(Edit::Spawn { x, y, p_type });
+        self.counts.replace(self.particles[index].p_type, p_type);
+        self.particles[index] = Particle::new(p_type, color_seed(index, self.tick));
+        self.temperatures[index] = p_type.material().temperature;
+        self.chunks.wake(x, y);
+    }
+}
+
+/// Whether a cell lies on the wall border painted around new worlds.
+pub(crate) fn is_border(x: usize, y: usize, width: usize, height: usize) -> bool {
+    x == 0 || y == 0 || x == width - 1 || y == height - 1
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn for_each_chunk<F>(batch: &[(usize, usize)], concurrent: bool, f: F)
+where
+    F: Fn(&(usize, usize)) + Send + Sync,
+{
+    use rayon::prelude::*;
+
+    if concurrent {
+        batch.par_iter().for_each(f);
+    } else {
+        batch.iter().for_each(f);
+    }
+}
+
+/// Wasm has no thread pool set up, so the chunks of a pass run serially, with
+/// the same result as running them in parallel.
+#[cfg(target_arch = "wasm32")]
+fn for_each_chunk<F>(batch: &[(usize, usize)], _concurrent: bool, f: F)
+where
+    F: Fn(&(usize, usize)),
+{
+    batch.iter().for_each(f);
+}
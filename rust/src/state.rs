@@ -0,0 +1,323 @@
+//! Binary snapshots of a running simulation.
+//!
+//! Layout, all integers little-endian:
+//!
+//! | bytes               | contents                            |
+//! |---------------------|-------------------------------------|
+//! | 4                   | magic `SAND`                        |
+//! | 1                   | format version                      |
+//! | 4                   | width                               |
+//! | 4                   | height                              |
+//! | 1                   | global clock                        |
+//! | 8                   | tick                                |
+//! | 4                   | time (`f32`)                        |
+//! | 16                  | rng state                           |
+//! | 16                  | brush rng state                     |
+//! | 8                   | gravity x and y (`f32`)             |
+//! | 1                   | edge mode                           |
+//! | 1                   | scan order                          |
+//! | 1                   | parallel stepping (0 or 1)          |
+//! | 1                   | reactions respect protection (0, 1) |
+//! | width * height * 6  | particles                           |
+//! | width * height      | per-cell clocks                     |
+//! | width * height * 4  | per-cell temperatures (`f32`)       |
+//! | ceil(cells / 8)     | protection mask                     |
+//! | ceil(chunks / 8)    | awake chunk mask                    |
+//!
+//! Each particle is its type, age, color seed, flags and x and y velocity, one
+//! byte each. Masks have a bit per cell or chunk, eight to a byte starting
+//! from the lowest bit. Sleeping chunks stay asleep when loaded, so the
+//! restored world steps exactly like the original. Snapshots of any other
+//! version are rejected.
+
+use std::fmt;
+
+use crate::edge::EdgeMode;
+use crate::particle::Particle;
+use crate::particle::ParticleType;
+use crate::scan::ScanOrder;
+use crate::simulation::Simulation;
+use crate::stats::MaterialCounts;
+
+const MAGIC: &[u8; 4] = b"SAND";
+const VERSION: u8 = 1;
+const PARTICLE_LEN: usize = 6;
+const RNG_LEN: usize = 16;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateError {
+    Truncated,
+    TrailingData,
+    BadMagic,
+    UnsupportedVersion(u8),
+    SizeMismatch {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    UnknownParticle(u8),
+    BadRng,
+    Corrupt,
+    Image(String),
+    UnmatchedColor {
+        x: usize,
+        y: usize,
+    },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Truncated => write!(f, "data is truncated"),
+            StateError::TrailingData => write!(f, "data has trailing bytes"),
+            StateError::BadMagic => write!(f, "data is not in the expected format"),
+            StateError::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            StateError::SizeMismatch { expected, found } => write!(
+                f,
+                "data is {}x{} but the world is {}x{}",
+                found.0, found.1, expected.0, expected.1
+            ),
+            StateError::UnknownParticle(t) => write!(f, "unknown particle type {}", t),
+            StateError::BadRng => write!(f, "invalid rng state"),
+            StateError::Corrupt => write!(f, "data is corrupt"),
+            StateError::Image(e) => write!(f, "invalid image: {}", e),
+            StateError::UnmatchedColor { x, y } => {
+                write!(f, "pixel at {}, {} matches no material", x, y)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Reads a byte that must be 0 or 1.
+    pub(crate) fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt),
+        }
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub(crate) fn f32(&mut self) -> Result<f32, StateError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    /// Reads an unsigned LEB128 integer of at most 32 bits.
+    pub(crate) fn varint(&mut self) -> Result<u32, StateError> {
+        let mut value: u32 = 0;
+
+        for shift in (0..35).step_by(7) {
+            let byte = self.u8()?;
+            let bits = u32::from(byte & 0x7f);
+
+            if shift == 28 && bits > 0x0f {
+                return Err(StateError::Corrupt);
+            }
+
+            value |= bits << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(StateError::Corrupt)
+    }
+
+    pub(crate) fn finish(&self) -> Result<(), StateError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::TrailingData)
+        }
+    }
+}
+
+pub(crate) fn write_varint(data: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        data.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+
+    data.push(value as u8);
+}
+
+/// Packs a mask into bytes, eight entries each, lowest bit first.
+pub(crate) fn pack_mask(mask: &[bool]) -> Vec<u8> {
+    mask.chunks(8)
+        .map(|bits| {
+            bits.iter()
+                .enumerate()
+                .fold(0, |byte, (i, &bit)| byte | (bit as u8) << i)
+        })
+        .collect()
+}
+
+/// Unpacks the first `len` entries of a mask packed by `pack_mask`.
+pub(crate) fn unpack_mask(data: &[u8], len: usize) -> Vec<bool> {
+    (0..len).map(|i| data[i / 8] & 1 << (i % 8) != 0).collect()
+}
+
+impl Simulation {
+    pub fn save_state(&self) -> Vec<u8> {
+        let cells = self.width * self.height;
+        let mut data = Vec::with_capacity(42 + cells * (PARTICLE_LEN + 5) + cells.div_ceil(8));
+
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&(self.width as u32).to_le_bytes());
+        data.extend_from_slice(&(self.height as u32).to_le_bytes());
+        data.push(self.clock);
+        data.extend_from_slice(&self.tick.to_le_bytes());
+        data.extend_from_slice(&self.time.to_le_bytes());
+        data.extend(bincode::serialize(&self.rng).expect("rng state serializes"));
+        data.extend(bincode::serialize(&self.brush_rng).expect("rng state serializes"));
+        data.extend_from_slice(&self.gravity[0].to_le_bytes());
+        data.extend_from_slice(&self.gravity[1].to_le_bytes());
+        data.push(self.edge_mode as u8);
+        data.push(self.scan_order as u8);
+        data.push(self.parallel as u8);
+        data.push(self.reactions_respect_protection as u8);
+        for p in &self.particles {
+            data.extend_from_slice(&[
+                p.p_type.id(),
+                p.age,
+                p.seed,
+                p.flags,
+                p.velocity[0] as u8,
+                p.velocity[1] as u8,
+            ]);
+        }
+        data.extend_from_slice(&self.clocks);
+        for temperature in &self.temperatures {
+            data.extend_from_slice(&temperature.to_le_bytes());
+        }
+        data.extend(pack_mask(&self.protected));
+        data.extend(pack_mask(self.chunks.awake()));
+
+        data
+    }
+
+    /// Restores a snapshot taken by `save_state`. The world is left untouched
+    /// if the data is rejected.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader::new(data);
+
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let width = reader.u32()? as usize;
+        let height = reader.u32()? as usize;
+        if (width, height) != (self.width, self.height) {
+            return Err(StateError::SizeMismatch {
+                expected: (self.width, self.height),
+                found: (width, height),
+            });
+        }
+
+        let clock = reader.u8()?;
+        let tick = reader.u64()?;
+        let time = reader.f32()?;
+        let rng = bincode::deserialize(reader.bytes(RNG_LEN)?).map_err(|_| StateError::BadRng)?;
+        let brush_rng =
+            bincode::deserialize(reader.bytes(RNG_LEN)?).map_err(|_| StateError::BadRng)?;
+        let gravity = [reader.f32()?, reader.f32()?];
+        let edge_mode = EdgeMode::from_u8(reader.u8()?).ok_or(StateError::Corrupt)?;
+        let scan_order = ScanOrder::from_u8(reader.u8()?).ok_or(StateError::Corrupt)?;
+        let parallel = reader.bool()?;
+        let reactions_respect_protection = reader.bool()?;
+
+        let cells = width * height;
+        let particles = reader
+            .bytes(cells * PARTICLE_LEN)?
+            .chunks_exact(PARTICLE_LEN)
+            .map(|p| {
+                let p_type =
+                    ParticleType::from_u8(p[0]).ok_or(StateError::UnknownParticle(p[0]))?;
+                Ok(Particle {
+                    p_type,
+                    age: p[1],
+                    seed: p[2],
+                    flags: p[3],
+                    velocity: [p[4] as i8, p[5] as i8],
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let clocks = reader.bytes(cells)?.to_vec();
+        let temperatures = (0..cells)
+            .map(|_| reader.f32())
+            .collect::<Result<Vec<_>, _>>()?;
+        let protected = unpack_mask(reader.bytes(cells.div_ceil(8))?, cells);
+        let chunks = self.chunks.columns() * self.chunks.rows();
+        let awake = unpack_mask(reader.bytes(chunks.div_ceil(8))?, chunks);
+
+        reader.finish()?;
+
+        self.particles = particles;
+        self.clocks = clocks;
+        self.temperatures = temperatures;
+        self.protected = protected;
+        self.clock = clock;
+        let replaced_on = std::mem::replace(&mut self.tick, tick);
+        self.time = time;
+        self.rng = rng;
+        self.brush_rng = brush_rng;
+        self.gravity = gravity;
+        self.edge_mode = edge_mode;
+        self.scan_order = scan_order;
+        self.parallel = parallel;
+        self.reactions_respect_protection = reactions_respect_protection;
+        self.chunks.set_wrap(edge_mode == EdgeMode::Wrap);
+        self.history.clear();
+        self.counts = MaterialCounts::new(&self.particles);
+        self.chunks.set_awake(awake);
+        self.record_load(replaced_on);
+
+        Ok(())
+    }
+}
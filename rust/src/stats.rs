@@ -0,0 +1,57 @@
+//! Running totals of particles per material.
+//!
+//! The totals are kept up to date as cells change, by steps as well as by
+//! edits, so reading them never scans the grid. Parallel steps change cells
+//! from several threads at once, so the totals are atomic.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use crate::material::MATERIALS;
+use crate::particle::Particle;
+use crate::particle::ParticleType;
+use crate::simulation::Simulation;
+
+pub(crate) struct MaterialCounts {
+    counts: Vec<AtomicUsize>,
+}
+
+impl MaterialCounts {
+    /// Counts every particle of a grid.
+    pub(crate) fn new(particles: &[Particle]) -> MaterialCounts {
+        let counts = MaterialCounts {
+            counts: MATERIALS.iter().map(|_| AtomicUsize::new(0)).collect(),
+        };
+        for particle in particles {
+            counts.counts[particle.p_type.id() as usize].fetch_add(1, Ordering::Relaxed);
+        }
+
+        counts
+    }
+
+    /// Notes that a cell holding `before` now holds `after`.
+    pub(crate) fn replace(&self, before: ParticleType, after: ParticleType) {
+        if before != after {
+            self.counts[before.id() as usize].fetch_sub(1, Ordering::Relaxed);
+            self.counts[after.id() as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn get(&self, p_type: ParticleType) -> usize {
+        self.counts[p_type.id() as usize].load(Ordering::Relaxed)
+    }
+}
+
+impl Simulation {
+    /// Number of cells holding each material, indexed by material id.
+    pub fn material_counts(&self) -> Vec<usize> {
+        ParticleType::all()
+            .map(|p_type| self.counts.get(p_type))
+            .collect()
+    }
+
+    /// Number of cells holding `p_type`.
+    pub fn cells_of(&self, p_type: ParticleType) -> usize {
+        self.counts.get(p_type)
+    }
+}
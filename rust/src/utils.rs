@@ -1,4 +1,4 @@
-extern crate web_sys;
+#[cfg(feature = "webgl")]
 use web_sys::console;
 
 pub fn set_panic_hook() {
@@ -12,10 +12,14 @@ pub fn set_panic_hook() {
     console_error_panic_hook::set_once();
 }
 
+#[cfg(feature = "webgl")]
+#[allow(dead_code)]
 pub struct Timer<'a> {
     name: &'a str,
 }
 
+#[cfg(feature = "webgl")]
+#[allow(dead_code)]
 impl<'a> Timer<'a> {
     pub fn new(name: &'a str) -> Timer<'a> {
         console::time_with_label(name);
@@ -23,6 +27,7 @@ impl<'a> Timer<'a> {
     }
 }
 
+#[cfg(feature = "webgl")]
 impl<'a> Drop for Timer<'a> {
     fn drop(&mut self) {
         console::time_end_with_label(self.name);
@@ -0,0 +1,152 @@
+//! Compact run-length-encoded world files for sharing scenes.
+//!
+//! Only the material grid is stored; clocks, temperatures, time and rng state
+//! are not. The header is always uncompressed, all integers little-endian:
+//!
+//! | bytes | contents                                 |
+//! |-------|------------------------------------------|
+//! | 4     | magic `SRLE`                             |
+//! | 1     | format version                           |
+//! | 1     | flags, bit 0 set if the body is deflated |
+//! | 4     | width                                    |
+//! | 4     | height                                   |
+//!
+//! The body is a sequence of runs covering the grid in row-major order. Each
+//! run is a particle type byte followed by the run length as an unsigned
+//! LEB128 integer.
+
+use crate::heat::initial_temperatures;
+use crate::particle::color_seed;
+use crate::particle::Particle;
+use crate::particle::ParticleType;
+use crate::simulation::Simulation;
+use crate::state::write_varint;
+use crate::state::Reader;
+use crate::state::StateError;
+use crate::stats::MaterialCounts;
+
+const MAGIC: &[u8; 4] = b"SRLE";
+const VERSION: u8 = 1;
+const FLAG_DEFLATE: u8 = 1;
+
+// A particle byte plus a full five byte varint.
+const MAX_RUN_LEN: usize = 6;
+
+impl Simulation {
+    pub fn export_world(&self, compress: bool) -> Vec<u8> {
+        let mut runs = Vec::new();
+        let mut cells = self.particles.iter().map(|p| p.p_type);
+
+        if let Some(mut current) = cells.next() {
+            let mut length: u32 = 1;
+
+            for p_type in cells {
+                if p_type == current {
+                    length += 1;
+                } else {
+                    runs.push(current.id());
+                    write_varint(&mut runs, length);
+                    current = p_type;
+                    length = 1;
+                }
+            }
+
+            runs.push(current.id());
+            write_varint(&mut runs, length);
+        }
+
+        let mut data = Vec::with_capacity(14 + runs.len());
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+
+        if compress {
+            data.push(FLAG_DEFLATE);
+        } else {
+            data.push(0);
+        }
+
+        data.extend_from_slice(&(self.width as u32).to_le_bytes());
+        data.extend_from_slice(&(self.height as u32).to_le_bytes());
+
+        if compress {
+            data.extend(miniz_oxide::deflate::compress_to_vec(&runs, 6));
+        } else {
+            data.extend(runs);
+        }
+
+        data
+    }
+
+    /// Replaces the grid with one read by `export_world`. The world is left
+    /// untouched if the data is rejected.
+    pub fn import_world(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader::new(data);
+
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let flags = reader.u8()?;
+        if flags & !FLAG_DEFLATE != 0 {
+            return Err(StateError::Corrupt);
+        }
+
+        let width = reader.u32()? as usize;
+        let height = reader.u32()? as usize;
+        if (width, height) != (self.width, self.height) {
+            return Err(StateError::SizeMismatch {
+                expected: (self.width, self.height),
+                found: (width, height),
+            });
+        }
+
+        let cells = width * height;
+        let body = reader.rest();
+
+        let inflated;
+        let body = if flags & FLAG_DEFLATE != 0 {
+            inflated =
+                miniz_oxide::inflate::decompress_to_vec_with_limit(body, cells * MAX_RUN_LEN)
+                    .map_err(|_| StateError::Corrupt)?;
+            &inflated[..]
+        } else {
+            body
+        };
+
+        let mut runs = Reader::new(body);
+        let mut particles = Vec::with_capacity(cells);
+
+        while particles.len() < cells {
+            let t = runs.u8()?;
+            let p_type = ParticleType::from_u8(t).ok_or(StateError::UnknownParticle(t))?;
+            let length = runs.varint()? as usize;
+
+            if length == 0 || particles.len() + length > cells {
+                return Err(StateError::Corrupt);
+            }
+
+            let start = particles.len();
+            particles.extend(
+                (start..start + length)
+                    .map(|index| Particle::new(p_type, color_seed(index, self.tick))),
+            );
+        }
+
+        runs.finish()?;
+
+        self.particles = particles;
+        self.clocks = vec![self.clock; cells];
+        self.temperatures = initial_temperatures(&self.particles);
+        self.history.clear();
+        self.counts = MaterialCounts::new(&self.particles);
+        self.chunks.wake_all();
+        self.record_load(self.tick);
+
+        Ok(())
+    }
+}
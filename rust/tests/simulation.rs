@@ -0,0 +1,1266 @@
+use sand_wasm::{
+    Anchor, Connectivity, EdgeMode, ParticleType, Pattern, Recording, ScanOrder, Simulation,
+    SpawnMode, StateError, AMBIENT_TEMPERATURE, MATERIALS,
+};
+
+#[test]
+fn new_world_has_wall_border() {
+    let simulation = Simulation::new(16, 12);
+
+    for x in 0..16 {
+        assert_eq!(simulation.get(x, 0), ParticleType::Wall);
+        assert_eq!(simulation.get(x, 11), ParticleType::Wall);
+    }
+
+    for y in 0..12 {
+        assert_eq!(simulation.get(0, y), ParticleType::Wall);
+        assert_eq!(simulation.get(15, y), ParticleType::Wall);
+    }
+
+    assert_eq!(simulation.get(8, 6), ParticleType::Empty);
+}
+
+#[test]
+fn sand_falls_to_the_floor() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn(8, 1, ParticleType::Sand);
+
+    for _ in 0..20 {
+        simulation.step();
+    }
+
+    // landing may scatter it sideways
+    assert!((1..15).any(|x| simulation.get(x, 14) == ParticleType::Sand));
+    assert_eq!(simulation.get(8, 1), ParticleType::Empty);
+}
+
+#[test]
+fn sand_sinks_below_water() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn(8, 14, ParticleType::Water);
+    simulation.spawn(8, 13, ParticleType::Sand);
+
+    simulation.step();
+
+    assert_eq!(simulation.get(8, 14), ParticleType::Sand);
+}
+
+#[test]
+fn materials_layer_by_density() {
+    let mut simulation = Simulation::new(16, 16);
+    for x in 1..15 {
+        simulation.spawn(x, 10, ParticleType::Sand);
+        simulation.spawn(x, 11, ParticleType::Water);
+        simulation.spawn(x, 12, ParticleType::Water);
+        simulation.spawn(x, 13, ParticleType::Oil);
+        simulation.spawn(x, 14, ParticleType::Oil);
+    }
+
+    for _ in 0..200 {
+        simulation.step();
+    }
+
+    for x in 1..15 {
+        assert_eq!(simulation.get(x, 14), ParticleType::Sand);
+        assert_eq!(simulation.get(x, 13), ParticleType::Water);
+        assert_eq!(simulation.get(x, 12), ParticleType::Water);
+        assert_eq!(simulation.get(x, 11), ParticleType::Oil);
+        assert_eq!(simulation.get(x, 10), ParticleType::Oil);
+    }
+}
+
+#[test]
+fn fire_burns_out_over_time() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn(8, 8, ParticleType::Fire);
+
+    simulation.step();
+    simulation.step();
+    assert_eq!(simulation.get(8, 8), ParticleType::Fire);
+
+    for _ in 0..4 {
+        simulation.step();
+    }
+    assert_eq!(simulation.get(8, 8), ParticleType::Empty);
+}
+
+#[test]
+fn particles_carry_their_age_when_moving() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn(8, 1, ParticleType::Sand);
+
+    for _ in 0..5 {
+        simulation.step();
+    }
+
+    let particle = simulation
+        .particles()
+        .iter()
+        .find(|p| p.p_type == ParticleType::Sand)
+        .unwrap();
+    assert_eq!(simulation.get(8, 1), ParticleType::Empty);
+    assert_eq!(particle.age, 5);
+}
+
+#[test]
+fn heat_spreads_through_water() {
+    let mut simulation = Simulation::new(16, 16);
+    for x in 1..15 {
+        simulation.spawn(x, 14, ParticleType::Water);
+    }
+
+    simulation.paint_heat(4, 14, 0, 60.0);
+    for _ in 0..5 {
+        simulation.step();
+    }
+
+    assert!(simulation.temperature(6, 14) > AMBIENT_TEMPERATURE);
+    assert!(simulation.temperature(4, 14) < AMBIENT_TEMPERATURE + 60.0);
+}
+
+#[test]
+fn heat_spreads_into_sleeping_chunks() {
+    let mut simulation = Simulation::new(128, 64);
+    simulation.spawn_rect(1, 62, 126, 1, ParticleType::Sand);
+    for _ in 0..200 {
+        simulation.step();
+    }
+    assert_eq!(simulation.awake_chunks(), 0);
+
+    simulation.paint_heat(30, 62, 0, 500.0);
+    for _ in 0..50 {
+        simulation.step();
+    }
+
+    assert!(simulation.temperature(34, 62) > AMBIENT_TEMPERATURE + 1.0);
+    assert!(simulation.awake_chunks() > 0);
+}
+
+#[test]
+fn water_changes_phase_with_temperature() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn(4, 14, ParticleType::Water);
+    simulation.spawn(10, 14, ParticleType::Water);
+
+    simulation.paint_heat(4, 14, 0, 200.0);
+    simulation.paint_heat(10, 14, 0, -50.0);
+    simulation.step();
+
+    // the water may have flowed sideways, taking its heat along
+    let count = |p_type| {
+        simulation
+            .particles()
+            .iter()
+            .filter(|p| p.p_type == p_type)
+            .count()
+    };
+    assert_eq!(count(ParticleType::Steam), 1);
+    assert_eq!(count(ParticleType::Ice), 1);
+}
+
+#[test]
+fn sand_melts_into_glass() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn(8, 14, ParticleType::Sand);
+
+    simulation.paint_heat(8, 14, 0, 1000.0);
+    simulation.step();
+
+    assert_eq!(simulation.get(8, 14), ParticleType::Glass);
+}
+
+#[test]
+fn fire_ignites_plants_through_heat() {
+    let mut simulation = Simulation::new(16, 16);
+    for x in 1..15 {
+        simulation.spawn(x, 14, ParticleType::Plant);
+    }
+    simulation.spawn(1, 13, ParticleType::Fire);
+
+    for _ in 0..100 {
+        simulation.step();
+    }
+
+    assert!((1..15).all(|x| simulation.get(x, 14) != ParticleType::Plant));
+}
+
+fn sand_row(simulation: &Simulation) -> usize {
+    let index = simulation
+        .particles()
+        .iter()
+        .position(|p| p.p_type == ParticleType::Sand)
+        .unwrap();
+    index / simulation.width()
+}
+
+#[test]
+fn gravity_speeds_up_falling() {
+    let mut accelerating = Simulation::new(8, 256);
+    let mut steady = Simulation::new(8, 256);
+    // too weak to speed anything up, but still pointing down
+    steady.set_gravity(0.0, 0.01);
+
+    for simulation in [&mut accelerating, &mut steady].iter_mut() {
+        simulation.spawn(4, 1, ParticleType::Sand);
+        for _ in 0..30 {
+            simulation.step();
+        }
+    }
+
+    assert_eq!(sand_row(&steady), 31);
+    assert!(sand_row(&accelerating) > 80);
+}
+
+#[test]
+fn sideways_gravity_piles_sand_against_the_wall() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.set_gravity(0.25, 0.0);
+    simulation.spawn(2, 8, ParticleType::Sand);
+
+    for _ in 0..30 {
+        simulation.step();
+    }
+
+    assert!((1..15).any(|y| simulation.get(14, y) == ParticleType::Sand));
+}
+
+#[test]
+fn zero_gravity_leaves_particles_floating() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.set_gravity(0.0, 0.0);
+    simulation.spawn(8, 4, ParticleType::Sand);
+    simulation.spawn(4, 4, ParticleType::Water);
+
+    for _ in 0..10 {
+        simulation.step();
+    }
+
+    assert_eq!(simulation.get(8, 4), ParticleType::Sand);
+    assert_eq!(simulation.get(4, 4), ParticleType::Water);
+}
+
+#[test]
+fn gravity_angle_turns_the_world_upside_down() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.set_gravity_angle(180.0, 0.25);
+    simulation.spawn(8, 12, ParticleType::Sand);
+
+    for _ in 0..20 {
+        simulation.step();
+    }
+
+    assert!((1..15).any(|x| simulation.get(x, 1) == ParticleType::Sand));
+}
+
+#[test]
+fn extreme_gravity_is_clamped_and_bad_gravity_ignored() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.set_gravity(1e9, 0.0);
+    simulation.spawn(4, 8, ParticleType::Sand);
+    simulation.spawn(4, 10, ParticleType::Water);
+
+    for _ in 0..10 {
+        simulation.step();
+    }
+    assert_eq!(count(&simulation, ParticleType::Sand), 1);
+
+    simulation.set_gravity(f32::NAN, 1.0);
+    simulation.set_gravity(0.0, f32::INFINITY);
+    assert_eq!(simulation.gravity(), [1e9, 0.0]);
+}
+
+fn count(simulation: &Simulation, p_type: ParticleType) -> usize {
+    simulation
+        .particles()
+        .iter()
+        .filter(|p| p.p_type == p_type)
+        .count()
+}
+
+#[test]
+fn brushes_fill_their_shapes() {
+    let mut simulation = Simulation::new(32, 32);
+
+    simulation.spawn_rect(4, 4, 3, 2, ParticleType::Plant);
+    assert_eq!(count(&simulation, ParticleType::Plant), 6);
+
+    simulation.spawn_circle(16, 16, 2, ParticleType::Oil);
+    assert_eq!(count(&simulation, ParticleType::Oil), 13);
+
+    simulation.spawn_line((4, 20), (13, 20), 0, ParticleType::Sand);
+    assert_eq!(count(&simulation, ParticleType::Sand), 10);
+
+    simulation.spawn_line((4, 26), (13, 26), 1, ParticleType::Water);
+    assert_eq!(count(&simulation, ParticleType::Water), 3 * 10 + 2);
+
+    simulation.spawn_polygon(&[(20, 4), (28, 4), (20, 12)], ParticleType::Fire);
+    assert_eq!(count(&simulation, ParticleType::Fire), 28);
+}
+
+#[test]
+fn brushes_clip_to_the_world_and_keep_the_border() {
+    let mut simulation = Simulation::new(16, 16);
+    let walls = count(&simulation, ParticleType::Wall);
+
+    simulation.spawn_circle(0, 0, 4, ParticleType::Sand);
+    simulation.spawn_rect(-10, 12, 40, 40, ParticleType::Water);
+    simulation.spawn_polygon(&[(-5, -5), (30, -5), (30, 3), (-5, 3)], ParticleType::Oil);
+
+    assert_eq!(count(&simulation, ParticleType::Wall), walls);
+    assert_eq!(count(&simulation, ParticleType::Water), 14 * 3);
+    assert_eq!(count(&simulation, ParticleType::Oil), 14 * 2);
+}
+
+#[test]
+fn huge_brushes_only_walk_the_world() {
+    let mut simulation = Simulation::new(16, 16);
+
+    simulation.spawn_rect(-20000, -20000, 40000, 40000, ParticleType::Sand);
+    assert_eq!(count(&simulation, ParticleType::Sand), 14 * 14);
+
+    simulation.spawn_circle(8, 8, usize::MAX, ParticleType::Water);
+    assert_eq!(count(&simulation, ParticleType::Water), 14 * 14);
+
+    let mix = Pattern::Mix(vec![(ParticleType::Oil, 50)]);
+    simulation.fill_region(
+        isize::MIN,
+        isize::MIN,
+        usize::MAX,
+        usize::MAX,
+        ParticleType::Oil,
+        &mix,
+    );
+    assert_eq!(count(&simulation, ParticleType::Oil), 14 * 14);
+
+    simulation.clear(false);
+    simulation.spawn_line((-200_000_000, 5), (200_000_000, 5), 0, ParticleType::Sand);
+    assert_eq!(count(&simulation, ParticleType::Sand), 14);
+
+    // a line passing outside the world only reaches it with its radius
+    simulation.spawn_line(
+        (-200_000_000, -3),
+        (200_000_000, -3),
+        4,
+        ParticleType::Water,
+    );
+    assert_eq!(count(&simulation, ParticleType::Water), 14);
+    simulation.spawn_line((isize::MIN, -100), (isize::MAX, -100), 4, ParticleType::Oil);
+    assert_eq!(count(&simulation, ParticleType::Oil), 0);
+}
+
+#[test]
+fn each_brush_call_is_one_undo_step() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn_circle(8, 8, 3, ParticleType::Sand);
+
+    assert!(simulation.undo());
+    assert_eq!(count(&simulation, ParticleType::Sand), 0);
+    assert!(!simulation.can_undo());
+}
+
+#[test]
+fn strokes_join_far_apart_samples_as_one_undo_step() {
+    let mut simulation = Simulation::new(32, 32);
+
+    simulation.begin_stroke(ParticleType::Sand, 0);
+    simulation.stroke_to(2, 10, None);
+    simulation.stroke_to(29, 10, None);
+    simulation.stroke_to(29, 20, None);
+    simulation.end_stroke();
+
+    assert_eq!(count(&simulation, ParticleType::Sand), 28 + 10);
+    assert!(simulation.undo());
+    assert_eq!(count(&simulation, ParticleType::Sand), 0);
+    assert!(!simulation.can_undo());
+}
+
+#[test]
+fn strokes_far_off_the_world_only_walk_the_world() {
+    let mut simulation = Simulation::new(32, 32);
+
+    simulation.begin_stroke(ParticleType::Sand, 0);
+    simulation.stroke_to(10, 10, None);
+    simulation.stroke_to(100_000_000, 10, None);
+    simulation.end_stroke();
+
+    assert_eq!(count(&simulation, ParticleType::Sand), 21);
+}
+
+#[test]
+fn undo_ends_the_stroke_in_progress() {
+    let mut simulation = Simulation::new(32, 32);
+    simulation.spawn(5, 5, ParticleType::Wall);
+
+    simulation.begin_stroke(ParticleType::Sand, 0);
+    simulation.stroke_to(2, 10, None);
+    assert!(simulation.undo());
+    assert!(!simulation.is_stroking());
+
+    // painting after the undo is not part of a stroke any more
+    simulation.stroke_to(10, 10, None);
+    assert_eq!(count(&simulation, ParticleType::Sand), 0);
+    assert_eq!(simulation.get(5, 5), ParticleType::Wall);
+}
+
+#[test]
+fn stroke_pressure_scales_the_radius() {
+    let mut simulation = Simulation::new(32, 32);
+
+    simulation.begin_stroke(ParticleType::Water, 4);
+    simulation.stroke_to(10, 10, Some(0.5));
+    simulation.end_stroke();
+    assert_eq!(count(&simulation, ParticleType::Water), 13);
+
+    // outside a stroke nothing is painted
+    simulation.stroke_to(20, 20, None);
+    assert_eq!(count(&simulation, ParticleType::Water), 13);
+}
+
+#[test]
+fn spawn_modes_choose_which_cells_to_write() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn_rect(4, 4, 8, 1, ParticleType::Glass);
+    simulation.spawn_rect(4, 6, 8, 1, ParticleType::Water);
+
+    simulation.set_spawn_mode(SpawnMode::OnlyEmpty);
+    simulation.spawn_rect(1, 1, 14, 14, ParticleType::Sand);
+    assert_eq!(count(&simulation, ParticleType::Glass), 8);
+    assert_eq!(count(&simulation, ParticleType::Water), 8);
+    assert_eq!(count(&simulation, ParticleType::Sand), 14 * 14 - 16);
+
+    simulation.set_spawn_mode(SpawnMode::Replace(ParticleType::Water));
+    simulation.spawn_circle(8, 8, 20, ParticleType::Oil);
+    simulation.spawn(4, 4, ParticleType::Oil);
+    assert_eq!(count(&simulation, ParticleType::Oil), 8);
+    assert_eq!(count(&simulation, ParticleType::Glass), 8);
+}
+
+#[test]
+fn spray_density_writes_a_share_of_the_cells() {
+    let mut simulation = Simulation::new(32, 32);
+
+    simulation.set_spray_density(0);
+    simulation.spawn_rect(1, 1, 30, 30, ParticleType::Sand);
+    assert_eq!(count(&simulation, ParticleType::Sand), 0);
+
+    simulation.set_spray_density(25);
+    simulation.spawn_rect(1, 1, 30, 30, ParticleType::Sand);
+    let sand = count(&simulation, ParticleType::Sand);
+    assert!((150..300).contains(&sand), "{} sand", sand);
+}
+
+#[test]
+fn spraying_is_reproducible() {
+    let spray = |simulation: &mut Simulation| {
+        simulation.set_spray_density(40);
+        simulation.spawn_rect(1, 1, 30, 30, ParticleType::Sand);
+        materials(simulation)
+    };
+
+    let mut simulation = Simulation::with_seed(32, 32, 3);
+    let saved = simulation.save_state();
+    let sprayed = spray(&mut simulation);
+    simulation.load_state(&saved).unwrap();
+    assert_eq!(spray(&mut simulation), sprayed);
+
+    let mut reseeded = Simulation::with_seed(32, 32, 9);
+    reseeded.reseed(3);
+    assert_eq!(spray(&mut reseeded), sprayed);
+}
+
+#[test]
+fn fill_patterns_lay_out_materials() {
+    let mut simulation = Simulation::new(32, 32);
+
+    simulation.fill_region(2, 2, 4, 4, ParticleType::Sand, &Pattern::Solid);
+    assert_eq!(count(&simulation, ParticleType::Sand), 16);
+
+    simulation.fill_region(
+        10,
+        2,
+        4,
+        4,
+        ParticleType::Oil,
+        &Pattern::Checkerboard(ParticleType::Plant),
+    );
+    assert_eq!(count(&simulation, ParticleType::Oil), 8);
+    assert_eq!(count(&simulation, ParticleType::Plant), 8);
+
+    let mix = Pattern::Mix(vec![(ParticleType::Water, 25)]);
+    simulation.fill_region(1, 10, 30, 20, ParticleType::Wall, &mix);
+    let water = count(&simulation, ParticleType::Water);
+    assert!((100..200).contains(&water), "{} water", water);
+    assert!(simulation.undo());
+    assert_eq!(count(&simulation, ParticleType::Water), 0);
+}
+
+#[test]
+fn fills_are_reproducible_and_leave_the_rng_alone() {
+    let fill = |simulation: &mut Simulation| {
+        let mix = Pattern::Mix(vec![(ParticleType::Sand, 50), (ParticleType::Water, 50)]);
+        simulation.fill_region(1, 1, 30, 10, ParticleType::Empty, &mix);
+    };
+
+    let mut a = Simulation::with_seed(32, 32, 7);
+    let mut b = Simulation::with_seed(32, 32, 7);
+    let mut c = Simulation::with_seed(32, 32, 7);
+    fill(&mut a);
+    fill(&mut b);
+    c.import_world(&a.export_world(false)).unwrap();
+    assert_eq!(materials(&a), materials(&b));
+
+    // a second fill in the same tick draws a different mix
+    let first = materials(&b);
+    b.clear(false);
+    fill(&mut b);
+    assert_ne!(materials(&b), first);
+
+    for _ in 0..20 {
+        a.step();
+        c.step();
+    }
+    assert_eq!(materials(&a), materials(&c));
+
+    // the brush rng is saved with the state and reset by reseeding
+    let saved = a.save_state();
+    fill(&mut a);
+    let filled = materials(&a);
+    a.load_state(&saved).unwrap();
+    fill(&mut a);
+    assert_eq!(materials(&a), filled);
+
+    let mut reseeded = Simulation::with_seed(32, 32, 1);
+    reseeded.reseed(7);
+    fill(&mut reseeded);
+    assert_eq!(materials(&reseeded), first);
+}
+
+#[test]
+fn flood_fill_stays_inside_its_container() {
+    let mut simulation = Simulation::new(16, 16);
+    // a box whose corners are missing, so it only leaks diagonally
+    simulation.spawn_rect(4, 3, 8, 1, ParticleType::Wall);
+    simulation.spawn_rect(4, 10, 8, 1, ParticleType::Wall);
+    simulation.spawn_rect(3, 4, 1, 6, ParticleType::Wall);
+    simulation.spawn_rect(12, 4, 1, 6, ParticleType::Wall);
+
+    let leaked = simulation.flood_fill(8, 6, ParticleType::Water, Connectivity::Eight, None);
+    assert!(leaked > 8 * 6);
+    assert_eq!(simulation.get(1, 1), ParticleType::Water);
+    simulation.undo();
+
+    let filled = simulation.flood_fill(8, 6, ParticleType::Water, Connectivity::Four, None);
+    assert_eq!(filled, 8 * 6);
+    assert_eq!(count(&simulation, ParticleType::Water), 8 * 6);
+
+    // and erasing the whole lake again
+    simulation.flood_fill(5, 5, ParticleType::Empty, Connectivity::Four, None);
+    assert_eq!(count(&simulation, ParticleType::Water), 0);
+}
+
+#[test]
+fn flood_fill_connectivity_and_limit() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn_line((2, 2), (8, 8), 0, ParticleType::Sand);
+
+    let four = simulation.flood_fill(2, 2, ParticleType::Oil, Connectivity::Four, None);
+    assert_eq!(four, 1);
+    simulation.undo();
+
+    let eight = simulation.flood_fill(2, 2, ParticleType::Oil, Connectivity::Eight, None);
+    assert_eq!(eight, 7);
+    simulation.undo();
+
+    let limited = simulation.flood_fill(2, 2, ParticleType::Oil, Connectivity::Eight, Some(3));
+    assert_eq!(limited, 3);
+    assert_eq!(simulation.get(4, 4), ParticleType::Oil);
+    assert_eq!(simulation.get(5, 5), ParticleType::Sand);
+}
+
+#[test]
+fn protected_cells_survive_every_edit() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn_rect(4, 4, 8, 1, ParticleType::Glass);
+    simulation.protect_rect(4, 4, 8, 1, true);
+    let walls = count(&simulation, ParticleType::Wall);
+
+    simulation.spawn(0, 8, ParticleType::Sand);
+    simulation.spawn(5, 4, ParticleType::Sand);
+    simulation.spawn_circle(8, 4, 3, ParticleType::Water);
+    simulation.fill_region(0, 0, 16, 16, ParticleType::Oil, &Pattern::Solid);
+    simulation.clear(false);
+    assert_eq!(count(&simulation, ParticleType::Glass), 8);
+    assert_eq!(count(&simulation, ParticleType::Wall), walls);
+
+    // protection stops a flood fill like a wall, even across empty cells
+    simulation.protect_rect(1, 10, 14, 1, true);
+    let filled = simulation.flood_fill(8, 8, ParticleType::Water, Connectivity::Eight, None);
+    assert_eq!(filled, 14 * 9 - 8);
+    assert_eq!(count(&simulation, ParticleType::Water), filled);
+    assert_eq!(simulation.get(8, 12), ParticleType::Empty);
+    simulation.protect_rect(1, 10, 14, 1, false);
+
+    simulation.set_protected(5, 4, false);
+    simulation.spawn(5, 4, ParticleType::Sand);
+    assert_eq!(simulation.get(5, 4), ParticleType::Sand);
+}
+
+#[test]
+fn undo_and_redo_leave_protected_cells_alone() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn(4, 4, ParticleType::Glass);
+    simulation.set_protected(4, 4, true);
+
+    assert!(simulation.undo());
+    assert_eq!(simulation.get(4, 4), ParticleType::Glass);
+
+    simulation.set_protected(4, 4, false);
+    simulation.spawn(4, 4, ParticleType::Sand);
+    simulation.undo();
+    simulation.set_protected(4, 4, true);
+    assert!(simulation.redo());
+    assert_eq!(simulation.get(4, 4), ParticleType::Glass);
+}
+
+#[test]
+fn reactions_respect_protection_only_when_asked() {
+    let burn = |respect: bool| {
+        let mut simulation = Simulation::new(16, 16);
+        simulation.set_reactions_respect_protection(respect);
+        simulation.spawn_rect(1, 14, 14, 1, ParticleType::Plant);
+        simulation.protect_rect(8, 14, 7, 1, true);
+        simulation.spawn(1, 13, ParticleType::Fire);
+
+        for _ in 0..100 {
+            simulation.step();
+        }
+        count(&simulation, ParticleType::Plant)
+    };
+
+    assert_eq!(burn(false), 0);
+    assert_eq!(burn(true), 7);
+}
+
+#[test]
+fn protection_is_saved_with_the_state() {
+    let mut original = Simulation::new(16, 16);
+    original.protect_rect(2, 2, 3, 3, true);
+    original.set_protected(0, 0, false);
+
+    let mut restored = Simulation::new(16, 16);
+    restored.load_state(&original.save_state()).unwrap();
+
+    assert_eq!(restored.protected_cells(), original.protected_cells());
+    assert!(restored.is_protected(3, 3));
+    assert!(!restored.is_protected(0, 0));
+    assert!(restored.is_protected(0, 1));
+}
+
+fn assert_counts_match(simulation: &Simulation) {
+    let counts: Vec<usize> = ParticleType::all()
+        .map(|p_type| count(simulation, p_type))
+        .collect();
+    assert_eq!(simulation.material_counts(), counts);
+}
+
+#[test]
+fn material_counts_follow_steps_and_edits() {
+    for &parallel in [false, true].iter() {
+        let mut simulation = Simulation::new(48, 48);
+        simulation.set_parallel(parallel);
+        simulation.spawn_rect(1, 40, 46, 7, ParticleType::Plant);
+        simulation.spawn_rect(1, 30, 46, 4, ParticleType::Water);
+        simulation.spawn_rect(10, 10, 10, 4, ParticleType::Sand);
+        simulation.spawn(24, 39, ParticleType::Fire);
+        simulation.paint_heat(30, 20, 3, 900.0);
+        assert_counts_match(&simulation);
+
+        for _ in 0..60 {
+            simulation.step();
+        }
+        assert_counts_match(&simulation);
+
+        simulation.undo();
+        simulation.resize(40, 40, Anchor::Center);
+        assert_counts_match(&simulation);
+    }
+}
+
+#[test]
+fn all_plants_burn_away() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn_rect(1, 14, 14, 1, ParticleType::Plant);
+    simulation.spawn(1, 13, ParticleType::Fire);
+    assert_eq!(simulation.cells_of(ParticleType::Plant), 14);
+
+    for _ in 0..100 {
+        simulation.step();
+    }
+
+    assert_eq!(simulation.cells_of(ParticleType::Plant), 0);
+}
+
+#[test]
+fn particle_reports_cell_state() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn(8, 1, ParticleType::Sand);
+    for _ in 0..3 {
+        simulation.step();
+    }
+
+    let (index, _) = simulation
+        .particles()
+        .iter()
+        .enumerate()
+        .find(|(_, p)| p.p_type == ParticleType::Sand)
+        .unwrap();
+    let particle = simulation.particle(index % 16, index / 16);
+    assert_eq!(particle.p_type, ParticleType::Sand);
+    assert_eq!(particle.age, 3);
+    assert!(particle.velocity[1] > 0);
+}
+
+#[test]
+fn clear_empties_the_world_but_keeps_the_border() {
+    let mut simulation = Simulation::new(16, 16);
+    let border = count(&simulation, ParticleType::Wall);
+    simulation.spawn_rect(2, 2, 12, 4, ParticleType::Sand);
+    simulation.spawn_rect(2, 8, 12, 1, ParticleType::Wall);
+
+    simulation.clear(true);
+    assert_eq!(count(&simulation, ParticleType::Sand), 0);
+    assert_eq!(count(&simulation, ParticleType::Wall), border + 12);
+
+    simulation.clear(false);
+    assert_eq!(count(&simulation, ParticleType::Wall), border);
+}
+
+#[test]
+fn registry_ids_round_trip() {
+    for (id, material) in MATERIALS.iter().enumerate() {
+        assert_eq!(material.id.id() as usize, id);
+        assert_eq!(ParticleType::from_u8(id as u8), Some(material.id));
+    }
+
+    assert_eq!(ParticleType::from_u8(MATERIALS.len() as u8), None);
+}
+
+#[test]
+fn oil_floats_on_water() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn(8, 14, ParticleType::Oil);
+    simulation.spawn(8, 13, ParticleType::Water);
+
+    simulation.step();
+
+    assert_eq!(simulation.get(8, 14), ParticleType::Water);
+}
+
+#[test]
+fn liquids_spread_sideways_before_dropping_diagonally() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn(8, 13, ParticleType::Wall);
+    simulation.spawn(8, 12, ParticleType::Water);
+
+    simulation.step();
+
+    assert!([7, 9]
+        .iter()
+        .any(|&x| simulation.get(x, 12) == ParticleType::Water));
+}
+
+fn run_seeded(seed: u64) -> Simulation {
+    let mut simulation = Simulation::with_seed(32, 32, seed);
+
+    for x in 4..28 {
+        simulation.spawn(x, 2, ParticleType::Sand);
+        simulation.spawn(x, 3, ParticleType::Water);
+        simulation.spawn(x, 4, ParticleType::Oil);
+    }
+
+    for _ in 0..100 {
+        simulation.step();
+    }
+
+    simulation
+}
+
+#[test]
+fn same_seed_gives_identical_worlds() {
+    assert_eq!(run_seeded(7).particles(), run_seeded(7).particles());
+}
+
+#[test]
+fn reseeding_restarts_the_random_sequence() {
+    let mut a = Simulation::with_seed(32, 32, 1);
+    let mut b = Simulation::with_seed(32, 32, 2);
+    a.reseed(9);
+    b.reseed(9);
+
+    for x in 4..28 {
+        a.spawn(x, 2, ParticleType::Water);
+        b.spawn(x, 2, ParticleType::Water);
+    }
+
+    for _ in 0..50 {
+        a.step();
+        b.step();
+    }
+
+    assert_eq!(a.particles(), b.particles());
+}
+
+#[test]
+fn restored_state_steps_identically() {
+    let mut original = run_seeded(3);
+    let mut restored = Simulation::with_seed(32, 32, 99);
+    restored.load_state(&original.save_state()).unwrap();
+
+    for _ in 0..50 {
+        original.step();
+        restored.step();
+    }
+
+    assert_eq!(original.particles(), restored.particles());
+    assert_eq!(original.save_state(), restored.save_state());
+}
+
+#[test]
+fn restored_state_keeps_sleeping_chunks_asleep() {
+    let mut original = Simulation::new(128, 64);
+    original.spawn_rect(20, 20, 40, 10, ParticleType::Sand);
+    for _ in 0..300 {
+        original.step();
+    }
+    assert_eq!(original.awake_chunks(), 0);
+
+    original.spawn(100, 10, ParticleType::Water);
+    let mut restored = Simulation::new(128, 64);
+    restored.load_state(&original.save_state()).unwrap();
+    assert_eq!(restored.awake_chunks(), original.awake_chunks());
+
+    for _ in 0..30 {
+        original.step();
+        restored.step();
+    }
+
+    assert_eq!(original.save_state(), restored.save_state());
+}
+
+#[test]
+fn load_state_rejects_bad_data() {
+    let mut simulation = Simulation::new(32, 32);
+    let data = simulation.save_state();
+
+    assert_eq!(
+        simulation.load_state(&data[..data.len() - 1]),
+        Err(StateError::Truncated)
+    );
+    assert_eq!(
+        Simulation::new(16, 32).load_state(&data),
+        Err(StateError::SizeMismatch {
+            expected: (16, 32),
+            found: (32, 32),
+        })
+    );
+
+    let mut other_version = data.clone();
+    other_version[4] = 2;
+    assert_eq!(
+        simulation.load_state(&other_version),
+        Err(StateError::UnsupportedVersion(2))
+    );
+}
+
+/// The material layout, which is all world files and images keep.
+fn materials(simulation: &Simulation) -> Vec<ParticleType> {
+    simulation.particles().iter().map(|p| p.p_type).collect()
+}
+
+#[test]
+fn world_file_round_trips() {
+    let original = run_seeded(5);
+
+    for &compress in [false, true].iter() {
+        let data = original.export_world(compress);
+        let mut imported = Simulation::new(32, 32);
+        imported.import_world(&data).unwrap();
+
+        assert_eq!(materials(&original), materials(&imported));
+    }
+}
+
+#[test]
+fn world_file_rejects_unknown_materials() {
+    let mut simulation = Simulation::new(32, 32);
+    let mut data = simulation.export_world(false);
+    data[14] = 200;
+
+    assert_eq!(
+        simulation.import_world(&data),
+        Err(StateError::UnknownParticle(200))
+    );
+}
+
+#[test]
+fn png_round_trips() {
+    let original = run_seeded(11);
+    let mut imported = Simulation::new(32, 32);
+    imported.import_png(&original.export_png(), 0.0).unwrap();
+
+    assert_eq!(materials(&original), materials(&imported));
+}
+
+#[test]
+fn replay_reproduces_recorded_run() {
+    let mut original = Simulation::with_seed(32, 32, 21);
+    original.start_recording();
+
+    for tick in 0..60 {
+        if tick % 5 == 0 {
+            original.spawn(8 + tick / 5, 2, ParticleType::Sand);
+            original.spawn(20, 2, ParticleType::Water);
+        }
+
+        if tick == 30 {
+            original.reseed(4);
+        }
+
+        original.step();
+    }
+
+    let recording = Recording::from_bytes(&original.stop_recording().unwrap().to_bytes()).unwrap();
+
+    let mut replayed = Simulation::with_seed(32, 32, 21);
+    replayed.start_replay(recording).unwrap();
+
+    for _ in 0..60 {
+        replayed.step();
+    }
+
+    assert!(!replayed.is_replaying());
+    assert_eq!(original.save_state(), replayed.save_state());
+}
+
+#[test]
+fn replay_reproduces_setting_changes() {
+    let mut original = Simulation::with_seed(32, 32, 8);
+    original.spawn_rect(4, 4, 24, 6, ParticleType::Sand);
+    // protected water next to a plant, which only grows into it while
+    // reactions ignore protection
+    original.spawn_rect(4, 28, 24, 2, ParticleType::Water);
+    original.spawn_rect(4, 27, 24, 1, ParticleType::Plant);
+    original.protect_rect(4, 28, 24, 2, true);
+    original.start_recording();
+
+    for tick in 0..60 {
+        match tick {
+            5 => original.set_reactions_respect_protection(true),
+            10 => original.set_gravity_angle(90.0, 0.5),
+            20 => original.set_scan_order(ScanOrder::AlternatingRow),
+            30 => original.set_edge_mode(EdgeMode::Wrap),
+            40 => original.set_parallel(true),
+            _ => (),
+        }
+        original.step();
+    }
+
+    let recording = Recording::from_bytes(&original.stop_recording().unwrap().to_bytes()).unwrap();
+    let mut replayed = Simulation::with_seed(32, 32, 8);
+    replayed.start_replay(recording).unwrap();
+    for _ in 0..60 {
+        replayed.step();
+    }
+
+    assert_eq!(replayed.gravity(), original.gravity());
+    assert_eq!(original.save_state(), replayed.save_state());
+}
+
+#[test]
+fn replay_reproduces_imports_and_loads() {
+    let mut source = Simulation::new(32, 32);
+    source.spawn_rect(4, 4, 8, 8, ParticleType::Water);
+    let world = source.export_world(false);
+    let state = source.save_state();
+
+    let mut original = Simulation::with_seed(32, 32, 8);
+    original.start_recording();
+    for tick in 0..40 {
+        match tick {
+            10 => original.import_world(&world).unwrap(),
+            25 => original.load_state(&state).unwrap(),
+            _ => (),
+        }
+        original.step();
+    }
+
+    let recording = Recording::from_bytes(&original.stop_recording().unwrap().to_bytes()).unwrap();
+    let mut replayed = Simulation::with_seed(32, 32, 8);
+    replayed.start_replay(recording).unwrap();
+    for _ in 0..40 {
+        replayed.step();
+    }
+
+    assert_eq!(original.save_state(), replayed.save_state());
+}
+
+#[test]
+fn settings_are_saved_with_the_state() {
+    let mut original = Simulation::new(32, 32);
+    original.set_gravity(0.5, -0.25);
+    original.set_edge_mode(EdgeMode::Void);
+    original.set_scan_order(ScanOrder::BottomUp);
+    original.set_parallel(true);
+    original.set_reactions_respect_protection(true);
+
+    let mut restored = Simulation::new(32, 32);
+    restored.load_state(&original.save_state()).unwrap();
+
+    assert_eq!(restored.gravity(), [0.5, -0.25]);
+    assert_eq!(restored.edge_mode(), EdgeMode::Void);
+    assert_eq!(restored.scan_order(), ScanOrder::BottomUp);
+    assert!(restored.is_parallel());
+    assert!(restored.reactions_respect_protection());
+}
+
+#[test]
+fn undo_and_redo_restore_a_transaction() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.set_protected(0, 5, false);
+
+    simulation.begin_transaction();
+    simulation.spawn(0, 5, ParticleType::Sand);
+    simulation.spawn(4, 5, ParticleType::Wall);
+    simulation.spawn(4, 5, ParticleType::Plant);
+    simulation.end_transaction();
+
+    assert!(simulation.undo());
+    assert_eq!(simulation.get(0, 5), ParticleType::Wall);
+    assert_eq!(simulation.get(4, 5), ParticleType::Empty);
+    assert!(!simulation.undo());
+
+    assert!(simulation.redo());
+    assert_eq!(simulation.get(0, 5), ParticleType::Sand);
+    assert_eq!(simulation.get(4, 5), ParticleType::Plant);
+    assert!(!simulation.redo());
+}
+
+#[test]
+fn history_limit_drops_oldest_transactions() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.set_history_limit(2);
+
+    simulation.spawn(4, 4, ParticleType::Wall);
+    simulation.spawn(5, 4, ParticleType::Wall);
+    simulation.spawn(6, 4, ParticleType::Wall);
+
+    assert!(simulation.undo());
+    assert!(simulation.undo());
+    assert!(!simulation.undo());
+    assert_eq!(simulation.get(4, 4), ParticleType::Wall);
+}
+
+#[test]
+fn settled_chunks_go_to_sleep() {
+    let mut simulation = Simulation::new(128, 128);
+    assert_eq!(simulation.awake_chunks(), 16);
+
+    for x in 40..80 {
+        simulation.spawn(x, 100, ParticleType::Sand);
+    }
+
+    for _ in 0..100 {
+        simulation.step();
+    }
+
+    assert_eq!(simulation.awake_chunks(), 0);
+
+    simulation.spawn(10, 10, ParticleType::Sand);
+    assert_eq!(simulation.awake_chunks(), 1);
+
+    simulation.step();
+    assert_eq!(simulation.get(10, 11), ParticleType::Sand);
+}
+
+fn run_parallel(threads: usize) -> Vec<u8> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap();
+
+    pool.install(|| {
+        let mut simulation = Simulation::with_seed(160, 96, 13);
+        simulation.set_parallel(true);
+
+        for x in 10..150 {
+            simulation.spawn(x, 5, ParticleType::Sand);
+            simulation.spawn(x, 6, ParticleType::Water);
+            simulation.spawn(x, 7, ParticleType::Oil);
+            simulation.spawn(x, 60, ParticleType::Plant);
+        }
+
+        simulation.spawn(80, 59, ParticleType::Fire);
+
+        for _ in 0..150 {
+            simulation.step();
+        }
+
+        simulation.save_state()
+    })
+}
+
+#[test]
+fn parallel_step_is_independent_of_thread_count() {
+    let single = run_parallel(1);
+
+    assert_eq!(single, run_parallel(4));
+    assert_eq!(single, run_parallel(7));
+}
+
+/// Collapses a centered block of `p_type` and returns how far, on average,
+/// its particles ended up from the center column.
+fn mean_drift(scan_order: ScanOrder, p_type: ParticleType, seed: u64) -> f64 {
+    let mut simulation = Simulation::with_seed(129, 64, seed);
+    simulation.set_scan_order(scan_order);
+
+    for x in 54..75 {
+        for y in 1..30 {
+            simulation.spawn(x, y, p_type);
+        }
+    }
+
+    for _ in 0..60 {
+        simulation.step();
+    }
+
+    let (mut count, mut moment) = (0, 0);
+    for x in 0..129 {
+        for y in 0..64 {
+            if simulation.get(x, y) == p_type {
+                count += 1;
+                moment += x as i64 - 64;
+            }
+        }
+    }
+
+    moment as f64 / count as f64
+}
+
+#[test]
+fn column_major_scan_drifts_left() {
+    assert!(mean_drift(ScanOrder::ColumnMajor, ParticleType::Water, 1) < -5.0);
+}
+
+#[test]
+fn unbiased_scan_orders_spread_symmetrically() {
+    let orders = [
+        ScanOrder::AlternatingTick,
+        ScanOrder::AlternatingRow,
+        ScanOrder::BottomUp,
+        ScanOrder::RandomColumns,
+    ];
+
+    for &scan_order in orders.iter() {
+        for &p_type in [ParticleType::Sand, ParticleType::Water].iter() {
+            let drift: f64 = (0..4)
+                .map(|seed| mean_drift(scan_order, p_type, seed))
+                .sum();
+
+            assert!(
+                (drift / 4.0).abs() < 1.5,
+                "{:?} {:?} drifted {}",
+                scan_order,
+                p_type,
+                drift / 4.0
+            );
+        }
+    }
+}
+
+fn without_floor(edge_mode: EdgeMode) -> Simulation {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.set_edge_mode(edge_mode);
+    if edge_mode == EdgeMode::Wall {
+        simulation.protect_rect(0, 15, 16, 1, false);
+        for x in 0..16 {
+            simulation.spawn(x, 15, ParticleType::Empty);
+        }
+    }
+
+    simulation
+}
+
+#[test]
+fn wall_edge_stops_particles() {
+    let mut simulation = without_floor(EdgeMode::Wall);
+    simulation.spawn(8, 15, ParticleType::Sand);
+    simulation.step();
+
+    assert_eq!(simulation.get(8, 15), ParticleType::Sand);
+}
+
+#[test]
+fn void_edge_deletes_particles() {
+    let mut simulation = without_floor(EdgeMode::Void);
+    simulation.spawn(8, 15, ParticleType::Sand);
+    simulation.step();
+
+    assert!(simulation
+        .particles()
+        .iter()
+        .all(|p| p.p_type != ParticleType::Sand));
+}
+
+#[test]
+fn wrap_edge_moves_particles_to_the_opposite_side() {
+    let mut simulation = without_floor(EdgeMode::Wrap);
+    simulation.spawn(8, 15, ParticleType::Sand);
+    simulation.step();
+
+    assert_eq!(simulation.get(8, 0), ParticleType::Sand);
+    assert_eq!(simulation.get(8, 15), ParticleType::Empty);
+}
+
+#[test]
+fn edge_mode_removes_and_restores_the_border() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn(8, 8, ParticleType::Sand);
+
+    simulation.set_edge_mode(EdgeMode::Void);
+    assert_eq!(count(&simulation, ParticleType::Wall), 0);
+    assert!(!simulation.protected_cells().iter().any(|&p| p));
+    assert_counts_match(&simulation);
+
+    simulation.set_edge_mode(EdgeMode::Wrap);
+    simulation.resize(20, 20, Anchor::Center);
+    assert_eq!(count(&simulation, ParticleType::Wall), 0);
+    assert_eq!(count(&simulation, ParticleType::Sand), 1);
+
+    simulation.set_edge_mode(EdgeMode::Wall);
+    assert_eq!(count(&simulation, ParticleType::Wall), 4 * 19);
+    assert_eq!(
+        simulation.protected_cells().iter().filter(|&&p| p).count(),
+        4 * 19
+    );
+    assert_counts_match(&simulation);
+}
+
+#[test]
+fn resize_keeps_contents_at_the_anchor() {
+    let mut simulation = Simulation::new(16, 16);
+    simulation.spawn(7, 14, ParticleType::Sand);
+
+    simulation.resize(32, 24, Anchor::Bottom);
+
+    assert_eq!((simulation.width(), simulation.height()), (32, 24));
+    assert_eq!(simulation.get(15, 22), ParticleType::Sand);
+    assert_eq!(simulation.get(23, 22), ParticleType::Empty);
+    assert_eq!(simulation.get(31, 10), ParticleType::Wall);
+    assert_eq!(simulation.get(15, 23), ParticleType::Wall);
+
+    simulation.resize(8, 8, Anchor::TopLeft);
+
+    assert_eq!(simulation.particles().len(), 64);
+    assert_eq!(simulation.get(7, 7), ParticleType::Wall);
+    assert_eq!(simulation.get(4, 4), ParticleType::Empty);
+}